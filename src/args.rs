@@ -1,20 +1,45 @@
+use anyhow::{Error, Result};
 use clap::{Parser, ValueEnum};
 use strum::Display;
 
+use crate::owner;
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Display)]
 pub enum Command {
     /// Compress the input
     C,
     /// Decompress the input
     X,
+    /// List the contents of the input
+    L,
+    /// Test the integrity of the input without extracting it
+    T,
+    /// Train a zstd dictionary on samples of the files in the input, for --dict
+    TrainDict,
+    /// Estimate the size and time of compressing the inputs with each format and level, from a
+    /// sample of the files, without writing an archive
+    Estimate,
 }
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
 pub enum CompressType {
     TARZSTD,
+    TARGZ,
+    TARXZ,
+    TARBZ2,
+    TARLZ4,
+    /// A tarball without compression
+    TAR,
     ZIP,
+    /// A single file compressed with zstd, without a tar wrapper
+    ZST,
 }
 
+/// Smallest and largest zstd job sizes, as `ZSTDMT_JOBSIZE_MIN` and `ZSTDMT_JOBSIZE_MAX`.
+const ZSTD_JOB_SIZE_MIN: u32 = 512 * 1024;
+const ZSTD_JOB_SIZE_MAX: u32 = 1024 * 1024 * 1024;
+
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 pub struct Args {
@@ -23,29 +48,612 @@ pub struct Args {
     pub command: Command,
 
     /// Set type of archive
-    #[arg(short = 't', long, default_value = "tarzstd")]
-    pub compress_type: CompressType,
-
-    /// Input path
-    pub input: String,
+    /// (defaults to tarzstd when compressing, detected from the input otherwise)
+    #[arg(short = 't', long)]
+    pub compress_type: Option<CompressType>,
 
-    /// Output path, must be a file
+    /// Input paths, `-` extracts an archive read from stdin. Without -o, a second path is the
+    /// output, `-` writes the archive to stdout
     /// (defaults to input path with compression extension)
+    #[arg(value_name = "INPUT", required_unless_present = "files_from")]
+    pub paths: Vec<String>,
+
+    /// Output path, needed to compress several inputs into one archive
+    #[arg(short = 'o', long = "output", value_name = "OUTPUT")]
     pub output: Option<String>,
 
+    /// Archive the input SRC under DEST instead of its base name, `.` being the top of the
+    /// archive (repeatable, only for c)
+    #[arg(long = "map", value_name = "SRC=DEST")]
+    pub map: Vec<String>,
+
+    /// Only extract the entries matching these paths or glob patterns, given after `--`
+    /// (only for x)
+    #[arg(last = true)]
+    pub patterns: Vec<String>,
+
     /// Log level
     #[arg(long = "ll", default_value_t = 2, value_parser = clap::value_parser!(u8).range(1..))]
     pub log_level: u8,
 
-    /// Compress level
-    #[arg(long = "l", short = 'l')]
-    pub compress_level: Option<u8>,
+    /// Compress level. Zstd takes negative fast levels, and 20 to 22 with --ultra
+    #[arg(long = "l", short = 'l', allow_negative_numbers = true)]
+    pub compress_level: Option<i32>,
+
+    /// Allow zstd levels 20 to 22, which take much more memory to compress and extract
+    #[arg(long = "ultra", default_value_t = false)]
+    pub ultra: bool,
 
     /// Disable long distance matching (only for zstd)
     #[arg(long = "noldm", default_value_t = false)]
     pub no_long_distance_matching: bool,
 
+    /// Long distance matching window of 2^N bytes, 27 by default, as `zstd --long` (only for
+    /// zstd)
+    #[arg(long = "long", value_name = "N", num_args = 0..=1, require_equals = true,
+        default_missing_value = "27", conflicts_with_all = ["no_long_distance_matching", "window_log"],
+        value_parser = clap::value_parser!(u32).range(10..=31))]
+    pub long: Option<u32>,
+
+    /// Zstd window of 2^N bytes, the farthest back a match can be (only for zstd)
+    #[arg(long = "window-log", value_name = "N",
+        value_parser = clap::value_parser!(u32).range(10..=31))]
+    pub window_log: Option<u32>,
+
+    /// Number of zstd compression threads, 1 compresses on the calling thread (defaults to the
+    /// number of cores, only for zstd)
+    #[arg(long = "zstd-threads", value_name = "N",
+        value_parser = clap::value_parser!(u32).range(1..=256))]
+    pub zstd_threads: Option<u32>,
+
+    /// Bytes of input each zstd thread compresses at a time, 0 picks it from the window (only
+    /// for zstd with several threads)
+    #[arg(long = "job-size", value_name = "BYTES")]
+    pub job_size: Option<u32>,
+
+    /// Overlap between zstd jobs, from 1 for none to 9 for a full window, 0 for the default
+    /// (only for zstd with several threads)
+    #[arg(long = "overlap-log", value_name = "N",
+        value_parser = clap::value_parser!(u32).range(0..=9))]
+    pub overlap_log: Option<u32>,
+
+    /// Write zstd frame content checksums, which t verifies (default)
+    #[arg(
+        long = "checksum",
+        default_value_t = false,
+        conflicts_with = "no_checksum"
+    )]
+    pub checksum: bool,
+
+    /// Leave out zstd frame content checksums
+    #[arg(long = "no-checksum", default_value_t = false)]
+    pub no_checksum: bool,
+
     /// Only size smaller than this will be read in parallel
     #[arg(long = "sfs", default_value = "10485760")]
     pub small_file_size: u64,
+
+    /// Most bytes of file data held in memory at once while reading files to compress or
+    /// entries to extract, decoded --frame-size frames included. Larger files and entries are
+    /// streamed in pieces instead, and a larger frame is decoded alone
+    #[arg(long = "max-memory", value_name = "BYTES",
+        value_parser = clap::value_parser!(u64).range(1..))]
+    pub max_memory: Option<u64>,
+
+    /// Write tarzstd as independent zstd frames of this many bytes of tar data, in the zstd
+    /// seekable format, so extraction decodes them in parallel (only for tarzstd, 64 KiB to
+    /// 128 MiB)
+    #[arg(long = "frame-size", value_name = "BYTES",
+        value_parser = clap::value_parser!(u64).range(64 * 1024..=128 * 1024 * 1024))]
+    pub frame_size: Option<u64>,
+
+    /// Write tarzstd in the zstd seekable format with an index of the members, so l and x of
+    /// selected entries jump to them (frames of --frame-size, 4 MiB by default)
+    #[arg(long = "seekable", default_value_t = false)]
+    pub seekable: bool,
+
+    /// Raise the zstd level between frames while writing the output is slower than compressing,
+    /// and lower it while compressing is slower, within MIN:MAX (1:19 by default). Starts at -l
+    /// and writes frames as --frame-size (only for tarzstd)
+    #[arg(long = "adapt", value_name = "MIN:MAX", num_args = 0..=1, require_equals = true,
+        default_missing_value = "1:19", allow_hyphen_values = true, value_parser = parse_adapt)]
+    pub adapt: Option<(i32, i32)>,
+
+    /// Pick the highest level expected to compress within this time, as 90s, 10m or 2h,
+    /// estimated by compressing a sample of the files first (only for tar archives)
+    #[arg(long = "time-budget", value_name = "DURATION", value_parser = parse_duration,
+        conflicts_with_all = ["compress_level", "adapt"])]
+    pub time_budget: Option<std::time::Duration>,
+
+    /// Pick the lowest level expected to compress to at most this many bytes, estimated by
+    /// compressing a sample of the files first (only for tar archives)
+    #[arg(long = "target-size", value_name = "BYTES",
+        conflicts_with_all = ["compress_level", "adapt"])]
+    pub target_size: Option<u64>,
+
+    /// Zstd dictionary to compress with, as made by train-dict, or to extract an archive
+    /// compressed with it (only for tarzstd)
+    #[arg(long = "dict", value_name = "FILE")]
+    pub dict: Option<std::path::PathBuf>,
+
+    /// Store the --dict dictionary at the start of the archive, so extracting it needs no --dict
+    /// (not with --frame-size, --seekable or --adapt, as seekable readers expect the first frame
+    /// of the seek table at the start)
+    #[arg(long = "embed-dict", default_value_t = false, requires = "dict",
+        conflicts_with_all = ["frame_size", "seekable", "adapt"])]
+    pub embed_dict: bool,
+
+    /// Size of the dictionary made by train-dict
+    #[arg(long = "dict-size", value_name = "BYTES", default_value_t = 112640,
+        value_parser = clap::value_parser!(u64).range(256..=16 * 1024 * 1024))]
+    pub dict_size: u64,
+
+    /// Archive the files symlinks point to instead of the links themselves
+    #[arg(long = "follow-symlinks", default_value_t = false)]
+    pub follow_symlinks: bool,
+
+    /// Leave out entries matching this glob pattern, patterns without `/` match names at any
+    /// depth (repeatable)
+    #[arg(long = "exclude", value_name = "PATTERN")]
+    pub exclude: Vec<String>,
+
+    /// Read exclude patterns from this file, one per line (repeatable)
+    #[arg(long = "exclude-from", value_name = "FILE")]
+    pub exclude_from: Vec<std::path::PathBuf>,
+
+    /// Only archive entries matching this glob pattern, or inside a matching directory
+    /// (repeatable)
+    #[arg(long = "include", value_name = "PATTERN")]
+    pub include: Vec<String>,
+
+    /// Leave out .git, .hg and .svn directories
+    #[arg(long = "exclude-vcs", default_value_t = false)]
+    pub exclude_vcs: bool,
+
+    /// Leave out the contents of directories tagged with a valid CACHEDIR.TAG file
+    #[arg(long = "exclude-caches", default_value_t = false)]
+    pub exclude_caches: bool,
+
+    /// Leave out entries ignored by .gitignore and .ignore files inside the input
+    #[arg(long = "respect-gitignore", default_value_t = false)]
+    pub respect_gitignore: bool,
+
+    /// Archive the paths listed in this file, or stdin for `-`, one per line, instead of walking
+    /// inputs. Directories are not recursed into, and the exclude and include options apply to
+    /// the listed paths (only for c)
+    #[arg(long = "files-from", value_name = "FILE")]
+    pub files_from: Option<std::path::PathBuf>,
+
+    /// Paths in the --files-from list are terminated by NUL instead of newline, as printed by
+    /// `find -print0`
+    #[arg(long = "null", default_value_t = false, requires = "files_from")]
+    pub null: bool,
+
+    /// Produce byte-identical archives for identical trees: entries are sorted by path, owners
+    /// are reset to 0 and modification times are clamped to $SOURCE_DATE_EPOCH when it is set
+    #[arg(long = "reproducible", default_value_t = false)]
+    pub reproducible: bool,
+
+    /// List entries as JSON objects, one per line (only for l)
+    #[arg(long = "json", default_value_t = false)]
+    pub json: bool,
+
+    /// Archive and restore `user.*` and `security.*` extended attributes (only for tarzstd)
+    #[arg(long = "xattrs", default_value_t = false)]
+    pub xattrs: bool,
+
+    /// Archive and restore POSIX ACLs (only for tarzstd)
+    #[arg(long = "acls", default_value_t = false)]
+    pub acls: bool,
+
+    /// Extract entries with absolute paths, `..` components or symlinks pointing outside the
+    /// output directory instead of rejecting them
+    #[arg(long = "allow-unsafe-paths", default_value_t = false)]
+    pub allow_unsafe_paths: bool,
+
+    /// Restore the owner of extracted files (default when running as root)
+    #[arg(
+        long = "same-owner",
+        default_value_t = false,
+        conflicts_with = "no_same_owner"
+    )]
+    pub same_owner: bool,
+
+    /// Extract files as yourself instead of restoring their owner
+    #[arg(long = "no-same-owner", default_value_t = false)]
+    pub no_same_owner: bool,
+
+    /// Restore owners by the stored uid/gid instead of looking up user and group names
+    #[arg(long = "numeric-owner", default_value_t = false)]
+    pub numeric_owner: bool,
+}
+
+/// Options controlling how archives are created.
+#[derive(Debug, Clone)]
+pub struct CompressOptions {
+    pub compress_level: i32,
+    pub no_long_distance_matching: bool,
+    /// Zstd window log, the default of the level when `None`
+    pub window_log: Option<u32>,
+    pub zstd_threads: u32,
+    pub job_size: Option<u32>,
+    pub overlap_log: Option<u32>,
+    pub checksum: bool,
+    pub small_file_size: u64,
+    /// Bytes of file data buffered at once, shared by every clone of the options
+    pub memory: crate::utils::MemoryBudget,
+    /// Size of the independent zstd frames, 0 for a single frame
+    pub frame_size: u64,
+    /// Lowest and highest zstd level of the frames, the level is fixed when `None`
+    pub adapt: Option<(i32, i32)>,
+    /// Zstd dictionary file to compress with
+    pub dictionary: Option<std::path::PathBuf>,
+    /// Whether the dictionary is stored in the archive
+    pub embed_dictionary: bool,
+    pub follow_symlinks: bool,
+    pub xattrs: bool,
+    pub acls: bool,
+    pub reproducible: bool,
+    /// Latest modification time stored in a reproducible archive, in seconds since the epoch
+    pub source_date_epoch: Option<u64>,
+    pub exclude: Vec<String>,
+    pub exclude_from: Vec<std::path::PathBuf>,
+    pub include: Vec<String>,
+    pub exclude_vcs: bool,
+    pub exclude_caches: bool,
+    pub respect_gitignore: bool,
+    /// List of the paths to archive instead of walking the sources, `-` for stdin
+    pub files_from: Option<std::path::PathBuf>,
+    /// Whether the list is NUL-separated
+    pub null: bool,
+}
+
+impl Default for CompressOptions {
+    fn default() -> Self {
+        Self {
+            compress_level: 3,
+            no_long_distance_matching: false,
+            window_log: None,
+            zstd_threads: num_cpus::get() as u32,
+            job_size: None,
+            overlap_log: None,
+            checksum: true,
+            small_file_size: 10 * 1024 * 1024,
+            memory: crate::utils::MemoryBudget::default(),
+            frame_size: 0,
+            adapt: None,
+            dictionary: None,
+            embed_dictionary: false,
+            follow_symlinks: false,
+            xattrs: false,
+            acls: false,
+            reproducible: false,
+            source_date_epoch: None,
+            exclude: Vec::new(),
+            exclude_from: Vec::new(),
+            include: Vec::new(),
+            exclude_vcs: false,
+            exclude_caches: false,
+            respect_gitignore: false,
+            files_from: None,
+            null: false,
+        }
+    }
+}
+
+impl CompressOptions {
+    /// Modification time to store for an entry, clamped to `source_date_epoch`.
+    pub fn mtime(&self, metadata: &std::fs::Metadata) -> Option<std::time::Duration> {
+        let mtime = metadata
+            .modified()
+            .ok()?
+            .duration_since(std::time::UNIX_EPOCH)
+            .ok()?;
+        match self.source_date_epoch {
+            Some(epoch) if mtime.as_secs() >= epoch => Some(std::time::Duration::from_secs(epoch)),
+            _ => Some(mtime),
+        }
+    }
+}
+
+/// Options controlling how archives are extracted.
+#[derive(Debug, Clone, Default)]
+pub struct ExtractOptions {
+    pub allow_unsafe_paths: bool,
+    pub same_owner: bool,
+    pub numeric_owner: bool,
+    pub xattrs: bool,
+    pub acls: bool,
+    /// Paths or glob patterns of the entries to extract, all entries when empty
+    pub patterns: Vec<String>,
+    /// Zstd dictionary file the archive was compressed with, unless it is embedded
+    pub dictionary: Option<std::path::PathBuf>,
+    /// Bytes of entry data buffered at once, shared by every clone of the options
+    pub memory: crate::utils::MemoryBudget,
+}
+
+impl Args {
+    /// Splits the positional paths into the inputs and the output, given by -o or else by a
+    /// second path. There are no inputs with --files-from, the only path is the output.
+    pub fn inputs_and_output(&self) -> Result<(Vec<String>, Option<String>), Error> {
+        if self.files_from.is_some() {
+            if self.command != Command::C {
+                return Err(Error::msg("Only c takes --files-from"));
+            }
+            return match (&self.output, self.paths.as_slice()) {
+                (Some(output), []) | (None, [output]) => Ok((Vec::new(), Some(output.clone()))),
+                _ => Err(Error::msg(
+                    "With --files-from, give only the output path, the inputs are listed",
+                )),
+            };
+        }
+        if self.command == Command::Estimate {
+            return match self.output {
+                Some(_) => Err(Error::msg("estimate writes no archive, it takes no -o")),
+                None => Ok((self.paths.clone(), None)),
+            };
+        }
+        let (inputs, output) = match (&self.output, self.paths.as_slice()) {
+            (Some(output), paths) => (paths.to_vec(), Some(output.clone())),
+            (None, [input]) => (vec![input.clone()], None),
+            (None, [input, output]) => (vec![input.clone()], Some(output.clone())),
+            (None, _) if self.command == Command::C => {
+                return Err(Error::msg(
+                    "Give the output with -o to compress several inputs into one archive",
+                ));
+            }
+            (None, paths) => (paths.to_vec(), None),
+        };
+        if inputs.len() > 1 && self.command != Command::C {
+            return Err(Error::msg(format!(
+                "Only c takes several inputs, got {:?}",
+                inputs
+            )));
+        }
+        Ok((inputs, output))
+    }
+
+    /// Fails for compression options that do not apply to `compress_type` or to each other.
+    pub fn check_compress_options(&self, compress_type: CompressType) -> Result<(), Error> {
+        let is_zstd = matches!(compress_type, CompressType::TARZSTD | CompressType::ZST);
+        let zstd_options = [
+            ("--ultra", self.ultra),
+            ("--long", self.long.is_some()),
+            ("--window-log", self.window_log.is_some()),
+            ("--zstd-threads", self.zstd_threads.is_some()),
+            ("--job-size", self.job_size.is_some()),
+            ("--overlap-log", self.overlap_log.is_some()),
+            ("--checksum", self.checksum),
+            ("--no-checksum", self.no_checksum),
+            ("--adapt", self.adapt.is_some()),
+        ];
+        if let (false, Some((name, _))) = (is_zstd, zstd_options.iter().find(|(_, set)| *set)) {
+            return Err(Error::msg(format!(
+                "{} only applies to zstd, -t tarzstd or zst",
+                name
+            )));
+        }
+
+        let level = self.compress_level.unwrap_or(3);
+        let levels = match self.adapt {
+            Some((min, max)) => vec![level, min, max],
+            None => vec![level],
+        };
+        let max_level = if self.ultra { 22 } else { 19 };
+        for level in levels {
+            match is_zstd {
+                true if level < zstd::zstd_safe::min_c_level() || level > 22 => {
+                    return Err(Error::msg(format!(
+                        "Zstd level {} is out of range, levels go from {} to 22",
+                        level,
+                        zstd::zstd_safe::min_c_level()
+                    )));
+                }
+                true if level > max_level => {
+                    return Err(Error::msg(format!(
+                        "Zstd level {} needs --ultra, levels above 19 take much more memory",
+                        level
+                    )));
+                }
+                false if !(0..=9).contains(&level) => {
+                    return Err(Error::msg(format!(
+                        "Level {} is out of range for {:?}, levels go from 0 to 9",
+                        level, compress_type
+                    )));
+                }
+                _ => {}
+            }
+        }
+
+        // Independent frames are compressed one per rayon thread, not by zstd's threads
+        let mt_options = [
+            ("--zstd-threads", self.zstd_threads.is_some()),
+            ("--job-size", self.job_size.is_some()),
+            ("--overlap-log", self.overlap_log.is_some()),
+        ];
+        for (name, _) in mt_options.iter().filter(|(_, set)| *set) {
+            if self.frame_size.is_some() || self.seekable || self.adapt.is_some() {
+                return Err(Error::msg(format!(
+                    "{} does not apply to --frame-size, --seekable and --adapt, which compress \
+                     each frame on its own thread",
+                    name
+                )));
+            }
+            if *name != "--zstd-threads" && self.zstd_threads == Some(1) {
+                return Err(Error::msg(format!(
+                    "{} needs more than one zstd thread, see --zstd-threads",
+                    name
+                )));
+            }
+        }
+        match self.job_size {
+            Some(job_size)
+                if job_size != 0
+                    && !(ZSTD_JOB_SIZE_MIN..=ZSTD_JOB_SIZE_MAX).contains(&job_size) =>
+            {
+                Err(Error::msg(format!(
+                    "--job-size {} is out of range, it is 0 or from {} to {}",
+                    job_size, ZSTD_JOB_SIZE_MIN, ZSTD_JOB_SIZE_MAX
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn compress_options(&self) -> CompressOptions {
+        CompressOptions {
+            compress_level: self.compress_level.unwrap_or(3),
+            no_long_distance_matching: self.no_long_distance_matching,
+            window_log: self.window_log.or(self.long),
+            zstd_threads: self.zstd_threads.unwrap_or(num_cpus::get() as u32),
+            job_size: self.job_size,
+            overlap_log: self.overlap_log,
+            checksum: !self.no_checksum,
+            small_file_size: self.small_file_size,
+            memory: crate::utils::MemoryBudget::new(self.max_memory),
+            frame_size: match (self.frame_size, self.seekable || self.adapt.is_some()) {
+                (Some(frame_size), _) => frame_size,
+                (None, true) => crate::frames::DEFAULT_FRAME_SIZE,
+                (None, false) => 0,
+            },
+            adapt: self.adapt,
+            dictionary: self.dict.clone(),
+            embed_dictionary: self.embed_dict,
+            follow_symlinks: self.follow_symlinks,
+            xattrs: self.xattrs,
+            acls: self.acls,
+            reproducible: self.reproducible,
+            source_date_epoch: self
+                .reproducible
+                .then(|| std::env::var("SOURCE_DATE_EPOCH").ok())
+                .flatten()
+                .and_then(|epoch| epoch.trim().parse().ok()),
+            exclude: self.exclude.clone(),
+            exclude_from: self.exclude_from.clone(),
+            include: self.include.clone(),
+            exclude_vcs: self.exclude_vcs,
+            exclude_caches: self.exclude_caches,
+            respect_gitignore: self.respect_gitignore,
+            files_from: self.files_from.clone(),
+            null: self.null,
+        }
+    }
+
+    pub fn extract_options(&self) -> ExtractOptions {
+        ExtractOptions {
+            allow_unsafe_paths: self.allow_unsafe_paths,
+            same_owner: self.same_owner || (!self.no_same_owner && owner::is_root()),
+            numeric_owner: self.numeric_owner,
+            xattrs: self.xattrs,
+            acls: self.acls,
+            patterns: self.patterns.clone(),
+            dictionary: self.dict.clone(),
+            memory: crate::utils::MemoryBudget::new(self.max_memory),
+        }
+    }
+}
+
+/// Parses the `MIN:MAX` levels of `--adapt`.
+fn parse_adapt(value: &str) -> Result<(i32, i32), String> {
+    let (min, max) = value
+        .split_once(':')
+        .ok_or_else(|| format!("expected MIN:MAX levels, got {:?}", value))?;
+    let parse = |level: &str| {
+        level
+            .trim()
+            .parse::<i32>()
+            .map_err(|_| format!("invalid level {:?}", level))
+    };
+    let (min, max) = (parse(min)?, parse(max)?);
+    if min > max {
+        return Err(format!(
+            "the lowest level {} is above the highest {}",
+            min, max
+        ));
+    }
+    Ok((min, max))
+}
+
+/// Parses a duration in seconds, or with an `s`, `m`, `h` or `d` suffix.
+fn parse_duration(value: &str) -> Result<std::time::Duration, String> {
+    let (number, unit) = match value.find(|c: char| c.is_ascii_alphabetic()) {
+        Some(index) => value.split_at(index),
+        None => (value, "s"),
+    };
+    let seconds = match unit {
+        "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        "d" => 86400.0,
+        _ => return Err(format!("unknown unit {:?}, use s, m, h or d", unit)),
+    };
+    let number = number
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|number| number.is_finite() && *number >= 0.0)
+        .ok_or_else(|| format!("invalid duration {:?}", value))?;
+    Ok(std::time::Duration::from_secs_f64(number * seconds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_compress_options() {
+        let check = |line: &str| {
+            let args = Args::try_parse_from(line.split_whitespace())?;
+            let compress_type = args.compress_type.unwrap_or(CompressType::TARZSTD);
+            args.check_compress_options(compress_type)
+        };
+
+        assert!(check("rpcc c in -l 19").is_ok());
+        assert!(check("rpcc c in -l -5").is_ok());
+        assert!(check("rpcc c in -l 22 --ultra").is_ok());
+        assert!(check("rpcc c in -t zst --long --zstd-threads 4 --job-size 0").is_ok());
+        assert!(check("rpcc c in --long=30 --overlap-log 9 --no-checksum").is_ok());
+        assert!(check("rpcc c in -t targz -l 9").is_ok());
+        assert!(check("rpcc c in --adapt").is_ok());
+        assert!(check("rpcc c in --adapt=-5:22 --ultra").is_ok());
+
+        let error = |line: &str| check(line).unwrap_err().to_string();
+        assert!(error("rpcc c in -l 20").contains("--ultra"));
+        assert!(error("rpcc c in -l 23 --ultra").contains("out of range"));
+        assert!(error("rpcc c in -t targz -l -1").contains("out of range"));
+        assert!(error("rpcc c in -t targz --long").contains("only applies to zstd"));
+        assert!(error("rpcc c in -t zip --checksum").contains("only applies to zstd"));
+        assert!(error("rpcc c in --zstd-threads 1 --job-size 0").contains("more than one"));
+        assert!(error("rpcc c in --seekable --zstd-threads 4").contains("--seekable"));
+        assert!(error("rpcc c in --job-size 1000").contains("out of range"));
+        assert!(error("rpcc c in --adapt=1:22").contains("--ultra"));
+        assert!(error("rpcc c in -t targz --adapt").contains("only applies to zstd"));
+        assert!(error("rpcc c in --adapt --zstd-threads 4").contains("--adapt"));
+
+        // Rejected by clap itself
+        assert!(check("rpcc c in --long=32").is_err());
+        assert!(check("rpcc c in --long --noldm").is_err());
+        assert!(check("rpcc c in --long --window-log 20").is_err());
+        assert!(check("rpcc c in --checksum --no-checksum").is_err());
+        assert!(check("rpcc c in --adapt=19:1").is_err());
+        assert!(check("rpcc c in --adapt=1").is_err());
+        assert!(check("rpcc c in --time-budget 10m -l 3").is_err());
+        assert!(check("rpcc c in --target-size 100 --adapt").is_err());
+        assert!(check("rpcc c in --time-budget 10x").is_err());
+        assert!(check("rpcc c in --dict d --embed-dict --seekable").is_err());
+        assert!(check("rpcc c in --dict d --embed-dict --frame-size 65536").is_err());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        let seconds = |value: &str| parse_duration(value).map(|duration| duration.as_secs_f64());
+        assert_eq!(seconds("90"), Ok(90.0));
+        assert_eq!(seconds("90s"), Ok(90.0));
+        assert_eq!(seconds("10m"), Ok(600.0));
+        assert_eq!(seconds("1.5h"), Ok(5400.0));
+        assert_eq!(seconds("1d"), Ok(86400.0));
+        assert!(seconds("-1m").is_err());
+        assert!(seconds("m").is_err());
+        assert!(seconds("10 minutes").is_err());
+    }
 }
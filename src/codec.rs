@@ -0,0 +1,360 @@
+use anyhow::{Error, Result};
+use rayon::prelude::*;
+use std::io::{Read, Write};
+
+use crate::args::{CompressOptions, CompressType};
+
+/// Size of the blocks gzip compresses in parallel, as `pigz` uses.
+const GZIP_BLOCK_SIZE: usize = 128 * 1024;
+
+/// Operating system byte of the gzip header for "unknown".
+const GZIP_OS_UNKNOWN: u8 = 255;
+
+/// Size of the deflate window, the most of a dictionary that can be used.
+const DEFLATE_WINDOW: usize = 32 * 1024;
+
+/// Stream codec a tarball is compressed with.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Codec {
+    Zstd,
+    Gzip,
+    Xz,
+    Bzip2,
+    Lz4,
+    /// Tar data as is, for plain tarballs
+    Uncompressed,
+}
+
+impl Codec {
+    /// Codec of a tar based archive type, `None` for the other types.
+    pub fn of(compress_type: CompressType) -> Option<Self> {
+        match compress_type {
+            CompressType::TARZSTD => Some(Codec::Zstd),
+            CompressType::TARGZ => Some(Codec::Gzip),
+            CompressType::TARXZ => Some(Codec::Xz),
+            CompressType::TARBZ2 => Some(Codec::Bzip2),
+            CompressType::TARLZ4 => Some(Codec::Lz4),
+            CompressType::TAR => Some(Codec::Uncompressed),
+            CompressType::ZIP | CompressType::ZST => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Codec::Zstd => "zstd",
+            Codec::Gzip => "gzip",
+            Codec::Xz => "xz",
+            Codec::Bzip2 => "bzip2",
+            Codec::Lz4 => "lz4",
+            Codec::Uncompressed => "uncompressed",
+        }
+    }
+
+    /// Creates an encoder writing to `output`. Zstd, gzip and xz compress on all cores.
+    pub fn encoder<W: Write>(self, output: W, options: &CompressOptions) -> Result<Encoder<W>> {
+        let level = options.compress_level;
+        let encoder = match self {
+            Codec::Zstd if options.frame_size > 0 => {
+                Encoder::ZstdFrames(crate::frames::FrameWriter::new(output, options)?)
+            }
+            Codec::Zstd => Encoder::Zstd(crate::zstd::encoder(output, options)?),
+            Codec::Gzip => Encoder::Gzip(GzipWriter::new(output, level.clamp(1, 9) as u32)?),
+            Codec::Xz => {
+                let stream = liblzma::stream::MtStreamBuilder::new()
+                    .threads(num_cpus::get() as u32)
+                    .preset(level.clamp(0, 9) as u32)
+                    .check(liblzma::stream::Check::Crc64)
+                    .encoder()?;
+                Encoder::Xz(liblzma::write::XzEncoder::new_stream(output, stream))
+            }
+            Codec::Bzip2 => Encoder::Bzip2(bzip2::write::BzEncoder::new(
+                output,
+                bzip2::Compression::new(level.clamp(1, 9) as u32),
+            )),
+            Codec::Lz4 => Encoder::Lz4(lz4_flex::frame::FrameEncoder::new(output)),
+            Codec::Uncompressed => Encoder::Uncompressed(output),
+        };
+        Ok(encoder)
+    }
+
+    /// Creates a decoder reading from `input`, through every concatenated stream. Independent
+    /// zstd frames are decoded in parallel, with the zstd dictionary at `dictionary` if any, as
+    /// many at once as fit in `memory`.
+    pub fn decoder<'a, R: Read + 'a>(
+        self,
+        input: R,
+        dictionary: Option<&std::path::Path>,
+        memory: &crate::utils::MemoryBudget,
+    ) -> Result<Box<dyn Read + 'a>, Error> {
+        let decoder: Box<dyn Read + 'a> = match self {
+            Codec::Zstd => Box::new(crate::frames::ParallelDecoder::new(
+                input,
+                crate::dict::load(dictionary)?,
+                memory.clone(),
+            )?),
+            Codec::Gzip => Box::new(flate2::read::MultiGzDecoder::new(input)),
+            Codec::Xz => Box::new(liblzma::read::XzDecoder::new_multi_decoder(input)),
+            Codec::Bzip2 => Box::new(bzip2::read::MultiBzDecoder::new(input)),
+            Codec::Lz4 => Box::new(lz4_flex::frame::FrameDecoder::new(input)),
+            Codec::Uncompressed => Box::new(input),
+        };
+        Ok(decoder)
+    }
+}
+
+/// Thread pool compressing or decoding the blocks of one stream, with as many threads as the
+/// current pool. Blocks are never handed to the global pool: its threads read the files to
+/// archive or write the extracted ones, and wait on the channels to and from the stream.
+pub fn thread_pool() -> std::io::Result<rayon::ThreadPool> {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(rayon::current_num_threads())
+        .build()
+        .map_err(std::io::Error::other)
+}
+
+/// Encoder of one of the codecs, to be finished once everything is written.
+pub enum Encoder<W: Write> {
+    Zstd(zstd::stream::write::Encoder<'static, W>),
+    ZstdFrames(crate::frames::FrameWriter<W>),
+    Gzip(GzipWriter<W>),
+    Xz(liblzma::write::XzEncoder<W>),
+    Bzip2(bzip2::write::BzEncoder<W>),
+    Lz4(lz4_flex::frame::FrameEncoder<W>),
+    Uncompressed(W),
+}
+
+impl<W: Write> Encoder<W> {
+    /// Writes the end of the stream and returns the output.
+    pub fn finish(self) -> Result<W, Error> {
+        let output = match self {
+            Encoder::Zstd(encoder) => encoder.finish()?,
+            Encoder::ZstdFrames(encoder) => encoder.finish()?,
+            Encoder::Gzip(encoder) => encoder.finish()?,
+            Encoder::Xz(encoder) => encoder.finish()?,
+            Encoder::Bzip2(encoder) => encoder.finish()?,
+            Encoder::Lz4(encoder) => encoder.finish()?,
+            Encoder::Uncompressed(mut output) => {
+                output.flush()?;
+                output
+            }
+        };
+        Ok(output)
+    }
+
+    /// Records that the tar member at `path` starts here, for encoders with a member index.
+    pub fn index_member(&mut self, path: &std::path::Path) {
+        if let Encoder::ZstdFrames(encoder) = self {
+            encoder.index_member(path);
+        }
+    }
+
+    /// Number of frames written at each zstd level, for encoders that adapt their level.
+    pub fn adapted_levels(&self) -> Option<Vec<(i32, u64)>> {
+        match self {
+            Encoder::ZstdFrames(encoder) => encoder.adapted_levels(),
+            _ => None,
+        }
+    }
+
+    fn inner(&mut self) -> &mut dyn Write {
+        match self {
+            Encoder::Zstd(encoder) => encoder,
+            Encoder::ZstdFrames(encoder) => encoder,
+            Encoder::Gzip(encoder) => encoder,
+            Encoder::Xz(encoder) => encoder,
+            Encoder::Bzip2(encoder) => encoder,
+            Encoder::Lz4(encoder) => encoder,
+            Encoder::Uncompressed(output) => output,
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.inner().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner().flush()
+    }
+}
+
+/// Gzip encoder compressing blocks of the stream on the rayon pool, like `pigz`. Each block is
+/// primed with the end of the previous one as dictionary and ends on a byte boundary with a
+/// sync flush, so the blocks join into a single deflate stream in one gzip member.
+pub struct GzipWriter<W: Write> {
+    output: W,
+    level: flate2::Compression,
+    block: Vec<u8>,
+    /// Full blocks waiting for a batch of one block per core
+    blocks: Vec<Vec<u8>>,
+    /// End of the last block written, the dictionary of the next one
+    dictionary: Vec<u8>,
+    crc: flate2::Crc,
+    header_written: bool,
+    pool: rayon::ThreadPool,
+}
+
+impl<W: Write> GzipWriter<W> {
+    pub fn new(output: W, level: u32) -> std::io::Result<Self> {
+        Ok(Self {
+            output,
+            level: flate2::Compression::new(level),
+            block: Vec::with_capacity(GZIP_BLOCK_SIZE),
+            blocks: Vec::new(),
+            dictionary: Vec::new(),
+            crc: flate2::Crc::new(),
+            header_written: false,
+            pool: thread_pool()?,
+        })
+    }
+
+    /// Compresses the pending blocks in parallel and writes them in order. The last block of
+    /// the stream ends the deflate stream.
+    fn write_blocks(&mut self, last: bool) -> std::io::Result<()> {
+        if !self.header_written {
+            // No name and no modification time, as `gzip -n`
+            self.output
+                .write_all(&[0x1F, 0x8B, 8, 0, 0, 0, 0, 0, 0, GZIP_OS_UNKNOWN])?;
+            self.header_written = true;
+        }
+
+        let blocks = std::mem::take(&mut self.blocks);
+        let count = blocks.len();
+        let dictionaries = std::iter::once(self.dictionary.as_slice())
+            .chain(blocks.iter().map(|block| dictionary_of(block)))
+            .collect::<Vec<_>>();
+        let level = self.level;
+        let compressed = self.pool.install(|| {
+            blocks
+                .par_iter()
+                .zip(dictionaries)
+                .enumerate()
+                .map(|(index, (block, dictionary))| {
+                    let flush = match last && index + 1 == count {
+                        true => flate2::FlushCompress::Finish,
+                        false => flate2::FlushCompress::Sync,
+                    };
+                    let mut crc = flate2::Crc::new();
+                    crc.update(block);
+                    Ok((deflate_block(block, dictionary, level, flush)?, crc))
+                })
+                .collect::<std::io::Result<Vec<_>>>()
+        })?;
+
+        for (data, crc) in compressed {
+            self.output.write_all(&data)?;
+            self.crc.combine(&crc);
+        }
+        if let Some(block) = blocks.last() {
+            self.dictionary = dictionary_of(block).to_vec();
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> std::io::Result<W> {
+        self.blocks.push(std::mem::take(&mut self.block));
+        self.write_blocks(true)?;
+        self.output.write_all(&self.crc.sum().to_le_bytes())?;
+        self.output.write_all(&self.crc.amount().to_le_bytes())?;
+        self.output.flush()?;
+        Ok(self.output)
+    }
+}
+
+impl<W: Write> Write for GzipWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = buf.len().min(GZIP_BLOCK_SIZE - self.block.len());
+        self.block.extend_from_slice(&buf[..len]);
+        if self.block.len() == GZIP_BLOCK_SIZE {
+            let block = std::mem::replace(&mut self.block, Vec::with_capacity(GZIP_BLOCK_SIZE));
+            self.blocks.push(block);
+            if self.blocks.len() >= self.pool.current_num_threads() {
+                self.write_blocks(false)?;
+            }
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.output.flush()
+    }
+}
+
+fn dictionary_of(block: &[u8]) -> &[u8] {
+    &block[block.len().saturating_sub(DEFLATE_WINDOW)..]
+}
+
+/// Deflates one block as raw deflate data, ending with `flush`. Sync flushes end on a byte
+/// boundary with an empty stored block, so the next block can follow directly.
+fn deflate_block(
+    block: &[u8],
+    dictionary: &[u8],
+    level: flate2::Compression,
+    flush: flate2::FlushCompress,
+) -> std::io::Result<Vec<u8>> {
+    let mut compress = flate2::Compress::new(level, false);
+    if !dictionary.is_empty() {
+        compress.set_dictionary(dictionary)?;
+    }
+    let mut output = Vec::with_capacity(block.len() / 2 + 64);
+    loop {
+        let consumed = compress.total_in() as usize;
+        let status = compress.compress_vec(&block[consumed..], &mut output, flush)?;
+        let done = compress.total_in() as usize == block.len()
+            && match flush {
+                flate2::FlushCompress::Finish => status == flate2::Status::StreamEnd,
+                // Flushed once output is left to spare
+                _ => output.len() < output.capacity(),
+            };
+        if done {
+            return Ok(output);
+        }
+        output.reserve(output.capacity().max(64));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codecs() {
+        let data = (0..3 * GZIP_BLOCK_SIZE + 123)
+            .map(|i| (i % 251) as u8 ^ (i / 4093) as u8)
+            .collect::<Vec<_>>();
+        for codec in [
+            Codec::Zstd,
+            Codec::Gzip,
+            Codec::Xz,
+            Codec::Bzip2,
+            Codec::Lz4,
+            Codec::Uncompressed,
+        ] {
+            for data in [&data[..], &[]] {
+                let mut encoder = codec
+                    .encoder(Vec::new(), &CompressOptions::default())
+                    .unwrap();
+                encoder.write_all(data).unwrap();
+                let compressed = encoder.finish().unwrap();
+
+                let mut decompressed = Vec::new();
+                codec
+                    .decoder(compressed.as_slice(), None, &Default::default())
+                    .unwrap()
+                    .read_to_end(&mut decompressed)
+                    .unwrap();
+                assert_eq!(decompressed, data, "{}", codec.name());
+
+                // Gzip blocks are joined into one member, which a single member decoder reads
+                if codec == Codec::Gzip {
+                    let mut decompressed = Vec::new();
+                    flate2::read::GzDecoder::new(compressed.as_slice())
+                        .read_to_end(&mut decompressed)
+                        .unwrap();
+                    assert_eq!(decompressed, data);
+                }
+            }
+        }
+    }
+}
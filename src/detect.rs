@@ -0,0 +1,222 @@
+use anyhow::{Context, Error, Result};
+use std::io::Read;
+
+use crate::args::CompressType;
+
+/// Container or stream formats that can be recognised from the head of an archive.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+    Zstd,
+    Zip,
+    Gzip,
+    Xz,
+    Bzip2,
+    Lz4,
+    Tar,
+}
+
+/// Number of leading bytes needed to recognise every format, including the ustar magic.
+pub const SNIFF_LEN: usize = 512;
+
+/// Recognises a format from the leading bytes of an archive.
+pub fn sniff(head: &[u8]) -> Option<Format> {
+    if head.len() >= 4 {
+        let magic = u32::from_le_bytes([head[0], head[1], head[2], head[3]]);
+        // Zstd frame or skippable frame
+        if magic == 0xFD2FB528 || magic & 0xFFFFFFF0 == 0x184D2A50 {
+            return Some(Format::Zstd);
+        }
+        if magic == 0x184D2204 {
+            return Some(Format::Lz4);
+        }
+        if head.starts_with(b"BZh") && (b'1'..=b'9').contains(&head[3]) {
+            return Some(Format::Bzip2);
+        }
+    }
+    if head.starts_with(b"PK\x03\x04") || head.starts_with(b"PK\x05\x06") {
+        return Some(Format::Zip);
+    }
+    if head.starts_with(&[0x1F, 0x8B]) {
+        return Some(Format::Gzip);
+    }
+    if head.starts_with(&[0xFD, b'7', b'z', b'X', b'Z', 0x00]) {
+        return Some(Format::Xz);
+    }
+    if head.len() >= 262 && &head[257..262] == b"ustar" {
+        return Some(Format::Tar);
+    }
+    None
+}
+
+/// Recognises a format from the extension of an archive path.
+pub fn from_extension(path: &std::path::Path) -> Option<Format> {
+    let name = path.file_name()?.to_string_lossy().to_lowercase();
+    let format = if name.ends_with(".zst") || name.ends_with(".zstd") || name.ends_with(".tzst") {
+        Format::Zstd
+    } else if name.ends_with(".zip") {
+        Format::Zip
+    } else if name.ends_with(".gz") || name.ends_with(".tgz") {
+        Format::Gzip
+    } else if name.ends_with(".xz") || name.ends_with(".txz") {
+        Format::Xz
+    } else if name.ends_with(".bz2") || name.ends_with(".tbz2") || name.ends_with(".tbz") {
+        Format::Bzip2
+    } else if name.ends_with(".lz4") {
+        Format::Lz4
+    } else if name.ends_with(".tar") {
+        Format::Tar
+    } else {
+        return None;
+    };
+    Some(format)
+}
+
+/// Reads enough of the start of an archive to detect its type: [`SNIFF_LEN`] bytes, or for
+/// zstd as many bytes as it takes to decode the first tar header.
+pub fn read_head<R: Read + ?Sized>(reader: &mut R) -> Result<Vec<u8>, Error> {
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    reader.take(SNIFF_LEN as u64).read_to_end(&mut head)?;
+
+    if sniff(&head) == Some(Format::Zstd) {
+        let mut rest = Vec::new();
+        let recorder = Recorder {
+            inner: reader,
+            copy: &mut rest,
+        };
+        let _ = decode_head(std::io::Cursor::new(head.clone()).chain(recorder));
+        head.extend(rest);
+    }
+    Ok(head)
+}
+
+/// Passes reads through, keeping a copy of the bytes read.
+struct Recorder<'a, R: ?Sized> {
+    inner: &'a mut R,
+    copy: &'a mut Vec<u8>,
+}
+
+impl<R: Read + ?Sized> Read for Recorder<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.copy.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// Decodes up to [`SNIFF_LEN`] bytes from a zstd stream, as far as the input goes.
+fn decode_head(input: impl Read) -> Vec<u8> {
+    let mut decoded = Vec::with_capacity(SNIFF_LEN);
+    if let Ok(decoder) = crate::zstd::decoder(std::io::BufReader::new(input), &[]) {
+        // A truncated input still leaves what was decoded before the error
+        let _ = decoder.take(SNIFF_LEN as u64).read_to_end(&mut decoded);
+    }
+    decoded
+}
+
+/// Detects the archive type of the file at `path`, preferring magic bytes over the extension.
+pub fn detect(path: &std::path::Path) -> Result<CompressType, Error> {
+    let mut file =
+        std::fs::File::open(path).with_context(|| format!("Failed to open file: {:?}", path))?;
+    let head = read_head(&mut file).with_context(|| format!("Failed to read file: {:?}", path))?;
+    detect_head(&head, path)
+}
+
+/// Detects the archive type from the head of the archive at `path`, which may be `-` for
+/// stdin, as read by [`read_head`].
+pub fn detect_head(head: &[u8], path: &std::path::Path) -> Result<CompressType, Error> {
+    let format = sniff(head)
+        .or_else(|| from_extension(path))
+        .ok_or_else(|| {
+            Error::msg(format!(
+                "Failed to detect archive type of {:?}, use -t to set it",
+                path
+            ))
+        })?;
+
+    match format {
+        // A plain zst file unless the decoded data starts with a tar header, or with the zero
+        // block ending an empty tarball. Only tarballs are compressed with a dictionary
+        Format::Zstd if sniff(head) == Some(Format::Zstd) => {
+            let decoded = decode_head(head);
+            let empty_tar = decoded.len() == SNIFF_LEN && decoded.iter().all(|byte| *byte == 0);
+            match sniff(&decoded) {
+                Some(Format::Tar) => Ok(CompressType::TARZSTD),
+                _ if empty_tar => Ok(CompressType::TARZSTD),
+                _ if crate::frames::needs_dictionary(head) => Ok(CompressType::TARZSTD),
+                _ => Ok(CompressType::ZST),
+            }
+        }
+        Format::Zstd => Ok(CompressType::TARZSTD),
+        Format::Gzip => Ok(CompressType::TARGZ),
+        Format::Xz => Ok(CompressType::TARXZ),
+        Format::Bzip2 => Ok(CompressType::TARBZ2),
+        Format::Lz4 => Ok(CompressType::TARLZ4),
+        Format::Zip => Ok(CompressType::ZIP),
+        Format::Tar => Ok(CompressType::TAR),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sniff() {
+        assert_eq!(
+            sniff(&zstd::encode_all(&b"data"[..], 3).unwrap()),
+            Some(Format::Zstd)
+        );
+        assert_eq!(sniff(b"PK\x03\x04rest"), Some(Format::Zip));
+        assert_eq!(sniff(&[0x1F, 0x8B, 0x08]), Some(Format::Gzip));
+        assert_eq!(sniff(b"\xFD7zXZ\x00rest"), Some(Format::Xz));
+        assert_eq!(sniff(b"BZh91AY&SY"), Some(Format::Bzip2));
+        assert_eq!(sniff(&[0x04, 0x22, 0x4D, 0x18, 0x64]), Some(Format::Lz4));
+
+        let mut tar_head = vec![0u8; SNIFF_LEN];
+        tar_head[257..262].copy_from_slice(b"ustar");
+        assert_eq!(sniff(&tar_head), Some(Format::Tar));
+
+        assert_eq!(sniff(b"plain text"), None);
+    }
+
+    #[test]
+    fn test_detect_head() {
+        let path = std::path::Path::new("-");
+
+        let mut tar = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(4);
+        tar.append_data(&mut header, "data.txt", &b"data"[..])
+            .unwrap();
+        let tar = tar.into_inner().unwrap();
+        let head = read_head(&mut tar.as_slice()).unwrap();
+        assert_eq!(detect_head(&head, path).unwrap(), CompressType::TAR);
+        let tar_zstd = zstd::encode_all(tar.as_slice(), 3).unwrap();
+        let head = read_head(&mut tar_zstd.as_slice()).unwrap();
+        assert_eq!(detect_head(&head, path).unwrap(), CompressType::TARZSTD);
+
+        let zst = zstd::encode_all(&b"plain data"[..], 3).unwrap();
+        let head = read_head(&mut zst.as_slice()).unwrap();
+        assert_eq!(detect_head(&head, path).unwrap(), CompressType::ZST);
+
+        // An embedded dictionary only comes with tarballs
+        let embedded = [crate::frames::dictionary_frame(b"dictionary"), zst].concat();
+        let head = read_head(&mut embedded.as_slice()).unwrap();
+        assert_eq!(detect_head(&head, path).unwrap(), CompressType::TARZSTD);
+
+        assert!(detect_head(b"plain text", path).is_err());
+    }
+
+    #[test]
+    fn test_from_extension() {
+        let format = |name: &str| from_extension(std::path::Path::new(name));
+        assert_eq!(format("a/test.tar.zst"), Some(Format::Zstd));
+        assert_eq!(format("test.ZIP"), Some(Format::Zip));
+        assert_eq!(format("test.tgz"), Some(Format::Gzip));
+        assert_eq!(format("test.tar.xz"), Some(Format::Xz));
+        assert_eq!(format("test.tbz2"), Some(Format::Bzip2));
+        assert_eq!(format("test.tar.lz4"), Some(Format::Lz4));
+        assert_eq!(format("test.tar"), Some(Format::Tar));
+        assert_eq!(format("test.txt"), None);
+    }
+}
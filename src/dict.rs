@@ -0,0 +1,120 @@
+use anyhow::{Context, Error, Result};
+use rayon::prelude::*;
+use std::io::Read;
+
+use crate::args::CompressOptions;
+use crate::{utils, walk};
+
+/// Bytes read from the start of each sampled file.
+const MAX_SAMPLE_SIZE: u64 = 128 * 1024;
+
+/// Samples to train on, in bytes per byte of dictionary, as zstd recommends.
+const SAMPLES_PER_DICT_BYTE: u64 = 100;
+
+/// Reads the dictionary at `path`, empty for no dictionary.
+pub fn load(path: Option<&std::path::Path>) -> Result<Vec<u8>, Error> {
+    match path {
+        Some(path) => {
+            std::fs::read(path).with_context(|| format!("Failed to read dictionary: {:?}", path))
+        }
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Trains a zstd dictionary of at most `dict_size` bytes on the files of the sources. Files
+/// are sampled evenly when there are more than the training needs, and only their start is
+/// read. Returns the dictionary.
+pub fn train(
+    sources: &[(std::path::PathBuf, std::path::PathBuf)],
+    options: &CompressOptions,
+    dict_size: u64,
+    log_level: u8,
+) -> Result<Vec<u8>, Error> {
+    let mut files = Vec::new();
+    for entry in walk::walk_sources(sources, options)? {
+        let (_, entry) = entry?;
+        if entry.file_type().is_file() {
+            let size = entry.metadata()?.len().min(MAX_SAMPLE_SIZE);
+            if size > 0 {
+                files.push((entry.into_path(), size));
+            }
+        }
+    }
+
+    // Every n-th file, to stay close to the sample size wanted
+    let total = files.iter().map(|(_, size)| size).sum::<u64>();
+    let step = total.div_ceil(dict_size * SAMPLES_PER_DICT_BYTE).max(1) as usize;
+    let samples = files
+        .par_iter()
+        .step_by(step)
+        .map(|(path, size)| {
+            let mut sample = Vec::with_capacity(*size as usize);
+            std::fs::File::open(path)
+                .and_then(|file| file.take(*size).read_to_end(&mut sample))
+                .with_context(|| format!("Failed to read sample: {:?}", path))?;
+            Ok(sample)
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    let sample_size = samples
+        .iter()
+        .map(|sample| sample.len() as u64)
+        .sum::<u64>();
+    let dictionary = zstd::dict::from_samples(&samples, dict_size as usize).with_context(|| {
+        format!(
+            "Failed to train a dictionary on {} samples of {}, which may be too few",
+            samples.len(),
+            utils::readable_bytes(sample_size)
+        )
+    })?;
+
+    if log_level >= 1 {
+        eprintln!(
+            "Trained a {} dictionary on {} of {} files ({})",
+            utils::readable_bytes(dictionary.len() as u64),
+            samples.len(),
+            files.len(),
+            utils::readable_bytes(sample_size)
+        );
+    }
+    Ok(dictionary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_train() {
+        let src_dir = tempfile::tempdir().unwrap();
+        for i in 0..500 {
+            let content = format!(
+                "{{\"id\": {}, \"name\": \"user{}\", \"active\": {}, \"tags\": [\"a\", \"b\"]}}\n",
+                i,
+                i * 7,
+                i % 2 == 0
+            );
+            std::fs::write(src_dir.path().join(format!("{}.json", i)), content).unwrap();
+        }
+        let sources = [(src_dir.path().to_path_buf(), std::path::PathBuf::new())];
+        let dictionary = train(&sources, &CompressOptions::default(), 4096, 0).unwrap();
+        assert!(!dictionary.is_empty() && dictionary.len() <= 4096);
+
+        // The dictionary makes small files smaller
+        let sample = std::fs::read(src_dir.path().join("42.json")).unwrap();
+        let plain = zstd::bulk::compress(&sample, 3).unwrap();
+        let mut compressor = zstd::bulk::Compressor::with_dictionary(3, &dictionary).unwrap();
+        let compressed = compressor.compress(&sample).unwrap();
+        assert!(compressed.len() < plain.len());
+        let mut decompressor = zstd::bulk::Decompressor::with_dictionary(&dictionary).unwrap();
+        assert_eq!(
+            decompressor.decompress(&compressed, sample.len()).unwrap(),
+            sample
+        );
+
+        let empty_dir = tempfile::tempdir().unwrap();
+        std::fs::write(empty_dir.path().join("empty"), b"").unwrap();
+        let sources = [(empty_dir.path().to_path_buf(), std::path::PathBuf::new())];
+        assert!(train(&sources, &CompressOptions::default(), 4096, 0).is_err());
+    }
+}
@@ -0,0 +1,395 @@
+use anyhow::{Context, Error, Result};
+use std::io::{Read, Seek};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::args::{CompressOptions, CompressType};
+use crate::codec::Codec;
+use crate::{utils, walk};
+
+/// Windows across the bytes of the tree that the sample is taken from.
+const SAMPLE_POINTS: u64 = 256;
+
+/// Bytes of each window, so the sample holds about 16 MiB.
+const CHUNK_SIZE: u64 = 64 * 1024;
+
+/// Levels tried for each codec, from the fastest to the smallest output. Lz4 and plain tar have
+/// no levels.
+pub fn levels(codec: Codec, ultra: bool) -> Vec<i32> {
+    match codec {
+        Codec::Zstd if ultra => vec![1, 3, 6, 9, 12, 15, 19, 22],
+        Codec::Zstd => vec![1, 3, 6, 9, 12, 15, 19],
+        Codec::Gzip | Codec::Bzip2 => vec![1, 3, 6, 9],
+        Codec::Xz => vec![0, 3, 6, 9],
+        Codec::Lz4 | Codec::Uncompressed => Vec::new(),
+    }
+}
+
+/// Files and chunks of files in evenly spaced windows over the bytes of the tree, archived
+/// through the tar pipeline to estimate the whole tree.
+pub struct Sample {
+    /// Regular files in the whole tree
+    pub files: u64,
+    /// Bytes of the regular files in the whole tree
+    pub size: u64,
+    /// Bytes of the files and chunks sampled
+    pub sample_size: u64,
+    /// Each sampled file or chunk as a source archived under the path of its file
+    sources: Vec<(PathBuf, PathBuf)>,
+    /// Holds the chunks of the larger files
+    _chunks: tempfile::TempDir,
+    options: CompressOptions,
+}
+
+/// Expected compressed size and compression time of the whole tree at one level.
+#[derive(Debug, Clone, Copy)]
+pub struct Estimate {
+    pub level: i32,
+    pub size: u64,
+    pub elapsed: Duration,
+}
+
+impl Sample {
+    /// Walks the sources as they are archived and samples [`SAMPLE_POINTS`] evenly spaced
+    /// windows of [`CHUNK_SIZE`] bytes over them, in walk order. Files of up to a window in a
+    /// window are taken whole, larger ones by a chunk per window, copied to a temporary
+    /// directory.
+    pub fn new(sources: &[(PathBuf, PathBuf)], options: &CompressOptions) -> Result<Self, Error> {
+        let mut files = Vec::new();
+        for entry in walk::walk_sources(sources, options)? {
+            let (rel_path, entry) = entry?;
+            if entry.file_type().is_file() {
+                let size = entry.metadata()?.len();
+                files.push((entry.into_path(), rel_path, size));
+            }
+        }
+        let size = files.iter().map(|(_, _, size)| size).sum::<u64>();
+        let step = size.div_ceil(SAMPLE_POINTS).max(1);
+
+        let chunks = tempfile::tempdir().context("Failed to create sample directory")?;
+        let mut sampled = Vec::new();
+        let mut sample_size = 0;
+        // Start of the next window, and of the current file, in the bytes of the tree
+        let (mut point, mut start) = (step / 2, 0);
+        for (path, rel_path, file_size) in &files {
+            let end = start + file_size;
+            while point + CHUNK_SIZE <= start {
+                point += step;
+            }
+            if point < end && *file_size <= CHUNK_SIZE {
+                sampled.push((path.clone(), rel_path.clone()));
+                sample_size += file_size;
+            }
+            while point < end && *file_size > CHUNK_SIZE {
+                let offset = point.saturating_sub(start).min(file_size - CHUNK_SIZE);
+                let chunk = chunks.path().join(sampled.len().to_string());
+                copy_chunk(path, offset, &chunk)?;
+                sampled.push((chunk, rel_path.clone()));
+                sample_size += CHUNK_SIZE;
+                // Windows within the chunk are sampled by it
+                while point < start + offset + CHUNK_SIZE {
+                    point += step;
+                }
+            }
+            start = end;
+        }
+
+        // The walk already left out the filtered entries, the sampled files are archived as is
+        let options = CompressOptions {
+            files_from: None,
+            exclude: Vec::new(),
+            exclude_from: Vec::new(),
+            include: Vec::new(),
+            exclude_vcs: false,
+            exclude_caches: false,
+            respect_gitignore: false,
+            ..options.clone()
+        };
+        Ok(Self {
+            files: files.len() as u64,
+            size,
+            sample_size,
+            sources: sampled,
+            _chunks: chunks,
+            options,
+        })
+    }
+
+    /// Compresses the sample to a tarball with `codec` at `level`, without writing it, and
+    /// scales the compressed size and the time taken to the whole tree.
+    pub fn estimate(&self, codec: Codec, level: i32) -> Result<Estimate, Error> {
+        self.measure(level, |output, options| {
+            crate::zstd::tar(codec, &self.sources, output, options, 0)
+        })
+    }
+
+    /// Compresses the sample to a zip archive, like [`Sample::estimate`].
+    pub fn estimate_zip(&self, level: i32) -> Result<Estimate, Error> {
+        self.measure(level, |output, options| {
+            crate::zip::zip_stream(&self.sources, output, options, 0)
+        })
+    }
+
+    fn measure(
+        &self,
+        level: i32,
+        compress: impl FnOnce(&mut utils::Counter<std::io::Sink>, &CompressOptions) -> Result<(), Error>,
+    ) -> Result<Estimate, Error> {
+        let options = CompressOptions {
+            compress_level: level,
+            ..self.options.clone()
+        };
+        let mut output = utils::Counter::new(std::io::sink());
+        let start = Instant::now();
+        compress(&mut output, &options)?;
+
+        let scale = self.size as f64 / self.sample_size.max(1) as f64;
+        Ok(Estimate {
+            level,
+            size: (output.count as f64 * scale) as u64,
+            elapsed: start.elapsed().mul_f64(scale),
+        })
+    }
+}
+
+/// Copies [`CHUNK_SIZE`] bytes of the file at `path` from `offset` to `chunk`.
+fn copy_chunk(path: &std::path::Path, offset: u64, chunk: &std::path::Path) -> Result<(), Error> {
+    let mut file =
+        std::fs::File::open(path).with_context(|| format!("Failed to open file: {:?}", path))?;
+    file.seek(std::io::SeekFrom::Start(offset))?;
+    let mut output = std::fs::File::create(chunk)
+        .with_context(|| format!("Failed to create file: {:?}", chunk))?;
+    std::io::copy(&mut file.take(CHUNK_SIZE), &mut output)
+        .with_context(|| format!("Failed to sample file: {:?}", path))?;
+    Ok(())
+}
+
+/// Limits of `--time-budget` and `--target-size` on the archive.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Budget {
+    pub time: Option<Duration>,
+    pub size: Option<u64>,
+}
+
+/// Picks the level of `codec` that meets the budget from estimates on a sample: the lowest
+/// level under the target size, otherwise the highest level within the time budget, which the
+/// sampling counts against. When no level meets the budget, the smallest output within the time
+/// budget is picked, or the fastest level. Prints the estimate of the level picked.
+pub fn pick_level(
+    codec: Codec,
+    sources: &[(PathBuf, PathBuf)],
+    options: &CompressOptions,
+    ultra: bool,
+    budget: Budget,
+    log_level: u8,
+) -> Result<Estimate, Error> {
+    let start = Instant::now();
+    let levels = levels(codec, ultra);
+    if levels.is_empty() {
+        return Err(Error::msg(format!(
+            "{} has no levels to pick for --time-budget or --target-size",
+            codec.name()
+        )));
+    }
+    let sample = Sample::new(sources, options)?;
+
+    let mut estimates = Vec::with_capacity(levels.len());
+    for level in levels {
+        let estimate = sample.estimate(codec, level)?;
+        if log_level >= 2 {
+            eprintln!(
+                "Sample level {:>3} : {:>8}, {:>6}",
+                level,
+                utils::readable_bytes(estimate.size),
+                utils::readable_elapse(estimate.elapsed.as_secs_f64()),
+            );
+        }
+        estimates.push(estimate);
+        // Higher levels only take longer, and the lowest level under the size is wanted
+        let remaining = budget.time.map(|time| time.saturating_sub(start.elapsed()));
+        if remaining.is_some_and(|time| estimate.elapsed > time)
+            || budget.size.is_some_and(|size| estimate.size <= size)
+        {
+            break;
+        }
+    }
+
+    let remaining = budget.time.map(|time| time.saturating_sub(start.elapsed()));
+    let in_time = |estimate: &&Estimate| remaining.is_none_or(|time| estimate.elapsed <= time);
+    let picked = match budget.size {
+        Some(size) => estimates
+            .iter()
+            .filter(in_time)
+            .find(|estimate| estimate.size <= size),
+        None => estimates.iter().rfind(in_time),
+    };
+    let estimate = match picked {
+        Some(estimate) => *estimate,
+        None => {
+            let closest = estimates
+                .iter()
+                .filter(in_time)
+                .min_by_key(|estimate| estimate.size)
+                .unwrap_or(&estimates[0]);
+            if log_level >= 1 {
+                eprintln!(
+                    "No {} level meets the budget, using the closest",
+                    codec.name()
+                );
+            }
+            *closest
+        }
+    };
+
+    if log_level >= 1 {
+        eprintln!(
+            "Budget level : {}, {:>8}, {:>6}, estimated from {} of {} in {} files",
+            estimate.level,
+            utils::readable_bytes(estimate.size),
+            utils::readable_elapse(estimate.elapsed.as_secs_f64()),
+            utils::readable_bytes(sample.sample_size),
+            utils::readable_bytes(sample.size),
+            sample.files,
+        );
+    }
+    Ok(estimate)
+}
+
+/// Prints a table of the expected size and time of compressing the sources to each of
+/// `compress_types`, at each level or only at `level`, from one sample of the files. Rows are
+/// printed as they are estimated. Lz4, plain tar and zip take no level.
+pub fn print_table(
+    sources: &[(PathBuf, PathBuf)],
+    options: &CompressOptions,
+    compress_types: &[CompressType],
+    level: Option<i32>,
+    ultra: bool,
+) -> Result<(), Error> {
+    let sample = Sample::new(sources, options)?;
+    print_line(format!(
+        "{} files, {}, estimated from a sample of {}",
+        sample.files,
+        utils::readable_bytes(sample.size),
+        utils::readable_bytes(sample.sample_size)
+    ))?;
+    print_line(format!(
+        "{:<8} {:>5} {:>9} {:>7} {:>7} {:>10}",
+        "FORMAT", "LEVEL", "SIZE", "RATIO", "TIME", "SPEED"
+    ))?;
+
+    for compress_type in compress_types {
+        let codec = Codec::of(*compress_type);
+        let levels = match (codec, level) {
+            (Some(Codec::Lz4 | Codec::Uncompressed) | None, _) => vec![None],
+            (Some(_), Some(level)) => vec![Some(level)],
+            (Some(codec), None) => levels(codec, ultra).into_iter().map(Some).collect(),
+        };
+        for level in levels {
+            let estimate = match codec {
+                Some(codec) => sample.estimate(codec, level.unwrap_or(options.compress_level))?,
+                None => sample.estimate_zip(options.compress_level)?,
+            };
+            let seconds = estimate.elapsed.as_secs_f64();
+            print_line(format!(
+                "{:<8} {:>5} {:>9} {:>6.2}x {:>7} {:>8}/s",
+                format!("{:?}", compress_type).to_lowercase(),
+                level.map_or("-".to_string(), |level| level.to_string()),
+                utils::readable_bytes(estimate.size),
+                sample.size as f64 / estimate.size.max(1) as f64,
+                utils::readable_elapse(seconds),
+                utils::readable_bytes((sample.size as f64 / seconds.max(1e-9)) as u64),
+            ))?;
+        }
+    }
+    Ok(())
+}
+
+/// Prints a line of the table to stdout, exiting quietly when the reader went away.
+fn print_line(line: String) -> Result<(), Error> {
+    use std::io::Write;
+    match writeln!(std::io::stdout().lock(), "{}", line) {
+        Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => std::process::exit(0),
+        result => Ok(result?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pick_level() {
+        let src_dir = tempfile::tempdir().unwrap();
+        for i in 0..200 {
+            let content = format!("line {} of a text file\n", i).repeat(i + 1);
+            std::fs::write(src_dir.path().join(format!("{}.txt", i)), content).unwrap();
+        }
+        // Words picked by a linear congruential generator, to compress like text
+        let words = [
+            "alpha ", "beta ", "gamma ", "delta ", "epsilon ", "zeta ", "eta\n",
+        ];
+        let mut state = 1u64;
+        let large = (0..200_000)
+            .flat_map(|_| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+                words[(state >> 33) as usize % words.len()].bytes()
+            })
+            .collect::<Vec<_>>();
+        std::fs::write(src_dir.path().join("large.bin"), large).unwrap();
+        std::fs::create_dir(src_dir.path().join("empty")).unwrap();
+        let sources = [(src_dir.path().to_path_buf(), PathBuf::new())];
+        let options = CompressOptions::default();
+
+        // The large file is sampled by chunks
+        let sample = Sample::new(&sources, &options).unwrap();
+        assert_eq!(sample.files, 201);
+        assert!(sample.sample_size > 0 && sample.sample_size < sample.size);
+        let mut archive = utils::Counter::new(std::io::sink());
+        crate::zstd::tar(Codec::Gzip, &sources, &mut archive, &options, 0).unwrap();
+        let estimate = sample.estimate(Codec::Gzip, 3).unwrap();
+        assert!(estimate.size.abs_diff(archive.count) < archive.count / 5);
+
+        let pick = |time: Option<u64>, size: Option<u64>| {
+            let budget = Budget {
+                time: time.map(Duration::from_secs),
+                size,
+            };
+            pick_level(Codec::Gzip, &sources, &options, false, budget, 0)
+                .unwrap()
+                .level
+        };
+        assert_eq!(pick(Some(3600), None), 9);
+        assert_eq!(pick(None, Some(u64::MAX)), 1);
+        assert_eq!(pick(Some(3600), Some(u64::MAX)), 1);
+        // Nothing is that small, the smallest output is picked
+        assert!(pick(None, Some(1)) > 1);
+        // Nothing is that fast, the fastest level is picked
+        assert_eq!(pick(Some(0), None), 1);
+
+        let budget = Budget {
+            time: Some(Duration::from_secs(60)),
+            size: None,
+        };
+        assert!(pick_level(Codec::Lz4, &sources, &options, false, budget, 0).is_err());
+    }
+
+    #[test]
+    fn test_estimate_zip() {
+        let src_dir = tempfile::tempdir().unwrap();
+        for i in 0..50 {
+            let content = format!("zip entry {}\n", i).repeat(100);
+            std::fs::write(src_dir.path().join(format!("{}.txt", i)), content).unwrap();
+        }
+        let sources = [(src_dir.path().to_path_buf(), PathBuf::new())];
+        let options = CompressOptions::default();
+
+        let sample = Sample::new(&sources, &options).unwrap();
+        let mut archive = utils::Counter::new(std::io::sink());
+        crate::zip::zip_stream(&sources, &mut archive, &options, 0).unwrap();
+        let estimate = sample.estimate_zip(options.compress_level).unwrap();
+        assert!(estimate.size.abs_diff(archive.count) < archive.count / 5);
+
+        let compress_types = [CompressType::TARLZ4, CompressType::ZIP];
+        assert!(print_table(&sources, &options, &compress_types, None, false).is_ok());
+    }
+}
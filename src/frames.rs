@@ -0,0 +1,811 @@
+use anyhow::{Error, Result};
+use rayon::prelude::*;
+use std::io::{Read, Seek, Write};
+
+use crate::args::CompressOptions;
+
+const ZSTD_MAGIC: u32 = 0xFD2FB528;
+
+/// Skippable frame magic holding the seek table, see the zstd seekable format
+const SEEK_TABLE_MAGIC: u32 = 0x184D2A5E;
+const SEEKABLE_MAGIC: u32 = 0x8F92EAB1;
+
+/// Skippable frame magic holding the member index, just before the seek table
+const MEMBER_INDEX_MAGIC: u32 = 0x184D2A5B;
+
+/// Skippable frame magic holding an embedded dictionary, at the start of the archive
+const DICTIONARY_MAGIC: u32 = 0x184D2A5C;
+
+/// Frame size of `--seekable` archives when `--frame-size` is not given.
+pub const DEFAULT_FRAME_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Frames of up to this many bytes are decoded in parallel, larger ones are streamed.
+pub const MAX_FRAME_SIZE: u64 = 128 * 1024 * 1024;
+
+/// Zstd encoder writing independent frames of `frame_size` bytes, compressed in parallel, in the
+/// zstd seekable format. A member index and the seek table are written at the end, each in a
+/// skippable frame, so `zstd -d` reads it like any zstd file.
+pub struct FrameWriter<W: Write> {
+    output: W,
+    level: i32,
+    checksum: bool,
+    window_log: Option<u32>,
+    frame_size: usize,
+    dictionary: Vec<u8>,
+    frame: Vec<u8>,
+    /// Full frames waiting for a batch of one frame per core
+    frames: Vec<Vec<u8>>,
+    /// Compressed and decompressed size of each frame written
+    seek_table: Vec<(u32, u32)>,
+    /// Bytes written so far, before compression
+    position: u64,
+    /// Path and offset of each tar member, before compression
+    members: Vec<(Vec<u8>, u64)>,
+    /// Lowest and highest level to adapt the level within, see [`FrameWriter::adapt`]
+    adapt: Option<(i32, i32)>,
+    /// Number of frames written at each level
+    levels: std::collections::BTreeMap<i32, u64>,
+    /// When the last batch was written, the wait for the next one is the time taken by input
+    written_at: std::time::Instant,
+    pool: rayon::ThreadPool,
+}
+
+impl<W: Write> FrameWriter<W> {
+    pub fn new(output: W, options: &CompressOptions) -> Result<Self, Error> {
+        // Seekable readers count the offsets of the frames in the seek table from the start
+        if options.embed_dictionary {
+            return Err(Error::msg(
+                "A dictionary cannot be embedded in independent frames, give it with --dict",
+            ));
+        }
+        let dictionary = crate::dict::load(options.dictionary.as_deref())?;
+        let frame_size = options.frame_size as usize;
+        let level = match options.adapt {
+            Some((min, max)) => options.compress_level.clamp(min, max),
+            None => options.compress_level,
+        };
+        Ok(Self {
+            output,
+            level,
+            checksum: options.checksum,
+            window_log: options.window_log,
+            frame_size,
+            dictionary,
+            frame: Vec::with_capacity(frame_size),
+            frames: Vec::new(),
+            seek_table: Vec::new(),
+            position: 0,
+            members: Vec::new(),
+            adapt: options.adapt,
+            levels: std::collections::BTreeMap::new(),
+            written_at: std::time::Instant::now(),
+            pool: crate::codec::thread_pool()?,
+        })
+    }
+
+    /// Number of frames at each level, when the level adapts. Frames not written yet count at
+    /// the current level, which [`FrameWriter::finish`] writes them at.
+    pub fn adapted_levels(&self) -> Option<Vec<(i32, u64)>> {
+        self.adapt?;
+        let mut levels = self.levels.clone();
+        let pending = self.frames.len() + usize::from(self.has_last_frame());
+        if pending > 0 {
+            *levels.entry(self.level).or_default() += pending as u64;
+        }
+        Some(levels.into_iter().collect())
+    }
+
+    /// Whether [`FrameWriter::finish`] writes a last, partial frame. An empty archive still
+    /// gets one frame.
+    fn has_last_frame(&self) -> bool {
+        !self.frame.is_empty() || self.seek_table.is_empty()
+    }
+
+    /// Picks the level of the next batch, like `zstd --adapt`: one level lower when compressing
+    /// took longer than waiting for input and writing the output, one level higher when it took
+    /// less than half as long, as there is time to spare for a better ratio.
+    fn adapt(&mut self, compress: std::time::Duration, io: std::time::Duration) {
+        let Some((min, max)) = self.adapt else {
+            return;
+        };
+        let step = if compress > io {
+            -1
+        } else if compress * 2 < io {
+            1
+        } else {
+            return;
+        };
+        let mut level = self.level + step;
+        // Level 0 is the default level, not the one between -1 and 1
+        if level == 0 {
+            level += step;
+        }
+        self.level = level.clamp(min, max);
+    }
+
+    /// Records that the tar member at `path` starts at the current position.
+    pub fn index_member(&mut self, path: &std::path::Path) {
+        self.members
+            .push((crate::walk::path_bytes(path), self.position));
+    }
+
+    /// Compresses the pending frames in parallel and writes them in order.
+    fn write_frames(&mut self) -> std::io::Result<()> {
+        if self.frames.is_empty() {
+            return Ok(());
+        }
+        let started_at = std::time::Instant::now();
+        let (level, checksum, window_log) = (self.level, self.checksum, self.window_log);
+        let frames = std::mem::take(&mut self.frames);
+        let count = frames.len() as u64;
+        let dictionary = &self.dictionary;
+        let compressed = self.pool.install(|| {
+            frames
+                .into_par_iter()
+                .map(|frame| {
+                    let mut compressor =
+                        zstd::bulk::Compressor::with_dictionary(level, dictionary)?;
+                    if let Some(window_log) = window_log {
+                        compressor.window_log(window_log)?;
+                    }
+                    // Lets `rpcc t` and `zstd -t` detect corruption
+                    compressor.include_checksum(checksum)?;
+                    Ok((compressor.compress(&frame)?, frame.len()))
+                })
+                .collect::<std::io::Result<Vec<_>>>()
+        })?;
+        let compressed_at = std::time::Instant::now();
+        for (data, size) in compressed {
+            self.output.write_all(&data)?;
+            self.seek_table.push((data.len() as u32, size as u32));
+        }
+        *self.levels.entry(level).or_default() += count;
+
+        let written_at = std::time::Instant::now();
+        self.adapt(
+            compressed_at - started_at,
+            (started_at - self.written_at) + (written_at - compressed_at),
+        );
+        self.written_at = written_at;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> std::io::Result<W> {
+        if self.has_last_frame() {
+            self.frames.push(std::mem::take(&mut self.frame));
+        }
+        self.write_frames()?;
+
+        let mut index = Vec::new();
+        for (path, offset) in &self.members {
+            index.extend_from_slice(&offset.to_le_bytes());
+            index.extend_from_slice(&(path.len() as u32).to_le_bytes());
+            index.extend_from_slice(path);
+        }
+        let index_size = u32::try_from(index.len()).map_err(|_| {
+            std::io::Error::other(format!(
+                "The member index of {} members takes more than the 4 GiB of a skippable frame",
+                self.members.len()
+            ))
+        })?;
+        self.output.write_all(&MEMBER_INDEX_MAGIC.to_le_bytes())?;
+        self.output.write_all(&index_size.to_le_bytes())?;
+        self.output.write_all(&index)?;
+
+        let mut table = Vec::with_capacity(self.seek_table.len() * 8 + 17);
+        table.extend_from_slice(&SEEK_TABLE_MAGIC.to_le_bytes());
+        table.extend_from_slice(&(self.seek_table.len() as u32 * 8 + 9).to_le_bytes());
+        for (compressed_size, size) in &self.seek_table {
+            table.extend_from_slice(&compressed_size.to_le_bytes());
+            table.extend_from_slice(&size.to_le_bytes());
+        }
+        table.extend_from_slice(&(self.seek_table.len() as u32).to_le_bytes());
+        // No checksums in the table, each frame carries its own
+        table.push(0);
+        table.extend_from_slice(&SEEKABLE_MAGIC.to_le_bytes());
+        self.output.write_all(&table)?;
+
+        self.output.flush()?;
+        Ok(self.output)
+    }
+}
+
+impl<W: Write> Write for FrameWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = buf.len().min(self.frame_size - self.frame.len());
+        self.frame.extend_from_slice(&buf[..len]);
+        self.position += len as u64;
+        if self.frame.len() == self.frame_size {
+            let frame = std::mem::replace(&mut self.frame, Vec::with_capacity(self.frame_size));
+            self.frames.push(frame);
+            if self.frames.len() >= self.pool.current_num_threads() {
+                self.write_frames()?;
+            }
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.output.flush()
+    }
+}
+
+/// Zstd decoder that decodes a batch of independent frames in parallel, one per core, and reads
+/// them out in order. Frames without a known size of at most [`MAX_FRAME_SIZE`], like the single
+/// frame of the multi-threaded encoder, are streamed through a regular decoder instead.
+///
+/// A dictionary embedded in the stream replaces the one given. Decoded frames are reserved from
+/// `memory`: a batch takes frames while they fit, and always at least one.
+pub struct ParallelDecoder<'a, R: Read + 'a> {
+    input: Option<R>,
+    dictionary: Vec<u8>,
+    /// Decoded frames with the memory they hold
+    decoded: std::collections::VecDeque<(std::io::Cursor<Vec<u8>>, crate::utils::Reservation)>,
+    /// Frame read that did not fit in the memory left, the first of the next batch
+    held: Option<(Vec<u8>, u64)>,
+    fallback: Option<Box<dyn Read + 'a>>,
+    memory: crate::utils::MemoryBudget,
+    pool: rayon::ThreadPool,
+}
+
+impl<'a, R: Read + 'a> ParallelDecoder<'a, R> {
+    pub fn new(
+        input: R,
+        dictionary: Vec<u8>,
+        memory: crate::utils::MemoryBudget,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            input: Some(input),
+            dictionary,
+            decoded: std::collections::VecDeque::new(),
+            held: None,
+            fallback: None,
+            memory,
+            pool: crate::codec::thread_pool()?,
+        })
+    }
+
+    /// Reads and decodes the next batch of frames, switching to the fallback decoder at the
+    /// first frame that cannot be decoded in memory.
+    fn fill(&mut self) -> Result<(), Error> {
+        let Some(input) = self.input.as_mut() else {
+            return Ok(());
+        };
+        let mut frames = Vec::new();
+        while frames.len() < self.pool.current_num_threads() {
+            let frame = match self.held.take() {
+                Some((data, size)) => Frame::Independent(data, size),
+                None => next_frame(input)?,
+            };
+            match frame {
+                Frame::End => {
+                    self.input = None;
+                    break;
+                }
+                Frame::Dictionary(dictionary) => self.dictionary = dictionary,
+                Frame::Independent(data, size) => {
+                    check_dictionary(&self.dictionary, &data)?;
+                    // The reader holds memory for the entries it is reading out of the frames,
+                    // so the first frame cannot wait for it
+                    let memory = match frames.is_empty() {
+                        true => self.memory.reserve_now(size),
+                        false => match self.memory.try_reserve(size) {
+                            Some(memory) => memory,
+                            None => {
+                                self.held = Some((data, size));
+                                break;
+                            }
+                        },
+                    };
+                    frames.push((data, size, memory));
+                }
+                Frame::Stream(head) => {
+                    check_dictionary(&self.dictionary, &head)?;
+                    let input = self.input.take().unwrap();
+                    let rest = std::io::Cursor::new(head).chain(input);
+                    let decoder =
+                        crate::zstd::decoder(std::io::BufReader::new(rest), &self.dictionary)?;
+                    self.fallback = Some(Box::new(decoder));
+                    break;
+                }
+            }
+        }
+
+        let dictionary = &self.dictionary;
+        let decoded = self.pool.install(|| {
+            frames
+                .into_par_iter()
+                .map(|(data, size, memory)| {
+                    let frame = zstd::bulk::Decompressor::with_dictionary(dictionary)?
+                        .decompress(&data, size as usize)?;
+                    Ok((std::io::Cursor::new(frame), memory))
+                })
+                .collect::<std::io::Result<Vec<_>>>()
+        })?;
+        self.decoded.extend(decoded);
+        Ok(())
+    }
+}
+
+impl<'a, R: Read + 'a> Read for ParallelDecoder<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            if let Some((frame, _)) = self.decoded.front_mut() {
+                let len = frame.read(buf)?;
+                if len > 0 || buf.is_empty() {
+                    return Ok(len);
+                }
+                self.decoded.pop_front();
+                continue;
+            }
+            if let Some(fallback) = self.fallback.as_mut() {
+                return fallback.read(buf);
+            }
+            if self.input.is_none() {
+                return Ok(0);
+            }
+            self.fill().map_err(std::io::Error::other)?;
+        }
+    }
+}
+
+/// Frame of a seekable archive, with its offsets in the compressed and decompressed data.
+struct SeekEntry {
+    compressed_offset: u64,
+    compressed_size: u64,
+    offset: u64,
+    size: u64,
+}
+
+/// Reader of the decompressed data of a seekable archive with a member index, decoding only the
+/// frames read. The last frame decoded is kept for the following reads.
+pub struct SeekableReader<R: Read + Seek> {
+    input: R,
+    dictionary: Vec<u8>,
+    frames: Vec<SeekEntry>,
+    /// Path and offset of each tar member, in archive order
+    pub members: Vec<(std::path::PathBuf, u64)>,
+    position: u64,
+    frame: Option<(usize, std::io::Cursor<Vec<u8>>)>,
+    /// Compressed bytes read
+    pub count: u64,
+}
+
+impl<R: Read + Seek> SeekableReader<R> {
+    /// Reads the seek table and the member index at the end of `input`. `None` when the input
+    /// has no seek table or no member index.
+    pub fn open(mut input: R, dictionary: Vec<u8>) -> Result<Option<Self>, Error> {
+        let len = input.seek(std::io::SeekFrom::End(0))?;
+        if len < 17 {
+            return Ok(None);
+        }
+
+        let mut footer = [0u8; 9];
+        input.seek(std::io::SeekFrom::Start(len - 9))?;
+        input.read_exact(&mut footer)?;
+        if u32::from_le_bytes(footer[5..9].try_into()?) != SEEKABLE_MAGIC {
+            return Ok(None);
+        }
+        let count = u32::from_le_bytes(footer[0..4].try_into()?) as u64;
+        // Entries carry a checksum when the top bit of the descriptor is set
+        let entry_size = if footer[4] & 0x80 != 0 { 12 } else { 8 };
+        let table_size = count * entry_size + 9;
+        let Some(table_start) = len.checked_sub(table_size + 8) else {
+            return Ok(None);
+        };
+        let mut table = vec![0u8; (table_size + 8) as usize];
+        input.seek(std::io::SeekFrom::Start(table_start))?;
+        input.read_exact(&mut table)?;
+        if u32::from_le_bytes(table[0..4].try_into()?) != SEEK_TABLE_MAGIC
+            || u32::from_le_bytes(table[4..8].try_into()?) as u64 != table_size
+        {
+            return Ok(None);
+        }
+
+        let mut frames = Vec::with_capacity(count as usize);
+        let (mut compressed_offset, mut offset) = (0, 0);
+        for entry in table[8..]
+            .chunks_exact(entry_size as usize)
+            .take(count as usize)
+        {
+            let compressed_size = u32::from_le_bytes(entry[0..4].try_into()?) as u64;
+            let size = u32::from_le_bytes(entry[4..8].try_into()?) as u64;
+            frames.push(SeekEntry {
+                compressed_offset,
+                compressed_size,
+                offset,
+                size,
+            });
+            compressed_offset += compressed_size;
+            offset += size;
+        }
+
+        // The member index fills the gap between the frames and the seek table
+        let Some(index_size) = table_start.checked_sub(compressed_offset + 8) else {
+            return Ok(None);
+        };
+        let mut index = vec![0u8; (index_size + 8) as usize];
+        input.seek(std::io::SeekFrom::Start(compressed_offset))?;
+        input.read_exact(&mut index)?;
+        if u32::from_le_bytes(index[0..4].try_into()?) != MEMBER_INDEX_MAGIC
+            || u32::from_le_bytes(index[4..8].try_into()?) as u64 != index_size
+        {
+            return Ok(None);
+        }
+        let mut members = Vec::new();
+        let mut rest = &index[8..];
+        while !rest.is_empty() {
+            if rest.len() < 12 {
+                return Err(Error::msg("Truncated member index"));
+            }
+            let offset = u64::from_le_bytes(rest[0..8].try_into()?);
+            let path_len = u32::from_le_bytes(rest[8..12].try_into()?) as usize;
+            let path = rest
+                .get(12..12 + path_len)
+                .ok_or_else(|| Error::msg("Truncated member index"))?;
+            members.push((crate::walk::path_from_bytes(path.to_vec()), offset));
+            rest = &rest[12 + path_len..];
+        }
+
+        Ok(Some(Self {
+            input,
+            dictionary,
+            frames,
+            members,
+            position: 0,
+            frame: None,
+            count: table_size + index_size + 16,
+        }))
+    }
+
+    fn size(&self) -> u64 {
+        self.frames
+            .last()
+            .map_or(0, |frame| frame.offset + frame.size)
+    }
+}
+
+impl<R: Read + Seek> Read for SeekableReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let index = self
+            .frames
+            .partition_point(|frame| frame.offset + frame.size <= self.position);
+        let Some(entry) = self.frames.get(index) else {
+            return Ok(0);
+        };
+        if !matches!(&self.frame, Some((cached, _)) if *cached == index) {
+            let mut data = vec![0u8; entry.compressed_size as usize];
+            self.input
+                .seek(std::io::SeekFrom::Start(entry.compressed_offset))?;
+            self.input.read_exact(&mut data)?;
+            self.count += entry.compressed_size;
+            let decoded = zstd::bulk::Decompressor::with_dictionary(&self.dictionary)?
+                .decompress(&data, entry.size as usize)?;
+            self.frame = Some((index, std::io::Cursor::new(decoded)));
+        }
+        let (_, frame) = self.frame.as_mut().unwrap();
+        frame.set_position(self.position - entry.offset);
+        let len = frame.read(buf)?;
+        self.position += len as u64;
+        Ok(len)
+    }
+}
+
+impl<R: Read + Seek> Seek for SeekableReader<R> {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        let position = match pos {
+            std::io::SeekFrom::Start(offset) => Some(offset),
+            std::io::SeekFrom::End(offset) => self.size().checked_add_signed(offset),
+            std::io::SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Seek before the start of the archive",
+            )
+        })?;
+        Ok(self.position)
+    }
+}
+
+enum Frame {
+    /// A dictionary embedded in a skippable frame
+    Dictionary(Vec<u8>),
+    /// A whole frame and its decompressed size
+    Independent(Vec<u8>, u64),
+    /// The bytes read of a frame to stream, from its start
+    Stream(Vec<u8>),
+    End,
+}
+
+/// Reads the next zstd frame, skipping skippable frames. The frame is only read whole when its
+/// header gives a decompressed size of at most [`MAX_FRAME_SIZE`].
+fn next_frame<R: Read>(input: &mut R) -> Result<Frame, Error> {
+    loop {
+        let mut magic = [0u8; 4];
+        let read = read_full(input, &mut magic)?;
+        if read == 0 {
+            return Ok(Frame::End);
+        }
+        if read < magic.len() {
+            return Err(Error::msg("Truncated zstd frame"));
+        }
+        let magic_number = u32::from_le_bytes(magic);
+
+        if magic_number & 0xFFFFFFF0 == 0x184D2A50 {
+            let mut size = [0u8; 4];
+            input.read_exact(&mut size)?;
+            let size = u32::from_le_bytes(size) as u64;
+            if magic_number == DICTIONARY_MAGIC {
+                let mut dictionary = Vec::new();
+                input.take(size).read_to_end(&mut dictionary)?;
+                if (dictionary.len() as u64) < size {
+                    return Err(Error::msg("Truncated zstd dictionary frame"));
+                }
+                return Ok(Frame::Dictionary(dictionary));
+            }
+            let skipped = std::io::copy(&mut input.take(size), &mut std::io::sink())?;
+            if skipped < size {
+                return Err(Error::msg("Truncated zstd skippable frame"));
+            }
+            continue;
+        }
+        if magic_number != ZSTD_MAGIC {
+            return Err(Error::msg("Unknown zstd frame magic"));
+        }
+
+        let mut frame = magic.to_vec();
+        let mut descriptor = [0u8; 1];
+        input.read_exact(&mut descriptor)?;
+        frame.push(descriptor[0]);
+        let descriptor = descriptor[0];
+        let single_segment = descriptor & 0x20 != 0;
+        let has_checksum = descriptor & 0x04 != 0;
+        let window_size = if single_segment { 0 } else { 1 };
+        let dictionary_size = [0, 1, 2, 4][(descriptor & 0x03) as usize];
+        let content_size_size = match descriptor >> 6 {
+            0 if single_segment => 1,
+            0 => 0,
+            1 => 2,
+            2 => 4,
+            _ => 8,
+        };
+        let start = frame.len();
+        frame.resize(start + window_size + dictionary_size + content_size_size, 0);
+        input.read_exact(&mut frame[start..])?;
+
+        let mut content_size = [0u8; 8];
+        content_size[..content_size_size]
+            .copy_from_slice(&frame[frame.len() - content_size_size..]);
+        let content_size = match content_size_size {
+            0 => None,
+            // The 2 byte field is offset by 256
+            2 => Some(u64::from_le_bytes(content_size) + 256),
+            _ => Some(u64::from_le_bytes(content_size)),
+        };
+        let size = match content_size {
+            Some(size) if size <= MAX_FRAME_SIZE => size,
+            _ => return Ok(Frame::Stream(frame)),
+        };
+
+        // Blocks, each behind a 3 byte header with its type and size
+        loop {
+            let mut header = [0u8; 3];
+            input.read_exact(&mut header)?;
+            frame.extend_from_slice(&header);
+            let header = u32::from_le_bytes([header[0], header[1], header[2], 0]);
+            let last = header & 1 != 0;
+            let block_size = match (header >> 1) & 0x03 {
+                // Raw and compressed blocks store their size, RLE blocks a single byte
+                0 | 2 => (header >> 3) as u64,
+                1 => 1,
+                _ => return Err(Error::msg("Reserved zstd block type")),
+            };
+            let read = input.take(block_size).read_to_end(&mut frame)?;
+            if (read as u64) < block_size {
+                return Err(Error::msg("Truncated zstd block"));
+            }
+            if last {
+                break;
+            }
+        }
+        if has_checksum {
+            let mut checksum = [0u8; 4];
+            input.read_exact(&mut checksum)?;
+            frame.extend_from_slice(&checksum);
+        }
+        return Ok(Frame::Independent(frame, size));
+    }
+}
+
+/// Skippable frame embedding `dictionary`, to write at the start of an archive.
+pub fn dictionary_frame(dictionary: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(dictionary.len() + 8);
+    frame.extend_from_slice(&DICTIONARY_MAGIC.to_le_bytes());
+    frame.extend_from_slice(&(dictionary.len() as u32).to_le_bytes());
+    frame.extend_from_slice(dictionary);
+    frame
+}
+
+/// Whether the zstd data starting with `head` needs a dictionary: it embeds one, or its first
+/// frame names one.
+pub fn needs_dictionary(head: &[u8]) -> bool {
+    if head.len() < 5 {
+        return false;
+    }
+    match u32::from_le_bytes([head[0], head[1], head[2], head[3]]) {
+        DICTIONARY_MAGIC => true,
+        ZSTD_MAGIC => head[4] & 0x03 != 0,
+        _ => false,
+    }
+}
+
+/// Fails for a frame that needs a dictionary when there is none.
+fn check_dictionary(dictionary: &[u8], frame: &[u8]) -> Result<(), Error> {
+    if dictionary.is_empty() && needs_dictionary(frame) {
+        return Err(Error::msg(
+            "The archive is compressed with a dictionary, give it with --dict",
+        ));
+    }
+    Ok(())
+}
+
+/// Reads until `buf` is full or the input ends, returning the bytes read.
+fn read_full<R: Read>(input: &mut R, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match input.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(len) => read += len,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frames() {
+        let data = (0..1_000_000u32)
+            .flat_map(|i| (i % 1000).to_le_bytes())
+            .collect::<Vec<_>>();
+        let options = CompressOptions {
+            frame_size: 64 * 1024,
+            ..Default::default()
+        };
+        let mut writer = FrameWriter::new(Vec::new(), &options).unwrap();
+        writer.write_all(&data).unwrap();
+        let compressed = writer.finish().unwrap();
+
+        // Regular decoders read every frame and skip the seek table
+        assert_eq!(zstd::decode_all(compressed.as_slice()).unwrap(), data);
+
+        let mut decoded = Vec::new();
+        let mut decoder =
+            ParallelDecoder::new(compressed.as_slice(), Vec::new(), Default::default()).unwrap();
+        decoder.read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, data);
+
+        // Frames without a known size are streamed
+        let stream = {
+            let mut encoder = zstd::stream::write::Encoder::new(Vec::new(), 3).unwrap();
+            encoder.multithread(2).unwrap();
+            encoder.write_all(&data).unwrap();
+            encoder.finish().unwrap()
+        };
+        let mut decoded = Vec::new();
+        let mut decoder =
+            ParallelDecoder::new(stream.as_slice(), Vec::new(), Default::default()).unwrap();
+        decoder.read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, data);
+
+        // Seeking decodes only the frames read
+        let mut writer = FrameWriter::new(Vec::new(), &options).unwrap();
+        writer.write_all(&data[..100]).unwrap();
+        writer.index_member(std::path::Path::new("a/b"));
+        writer.write_all(&data[100..]).unwrap();
+        let seekable = writer.finish().unwrap();
+        let mut reader = SeekableReader::open(std::io::Cursor::new(&seekable), Vec::new())
+            .unwrap()
+            .unwrap();
+        assert_eq!(reader.members, [(std::path::PathBuf::from("a/b"), 100)]);
+        // Seekable readers count the frames of the seek table from the start of the archive
+        assert_eq!(
+            u32::from_le_bytes(seekable[0..4].try_into().unwrap()),
+            ZSTD_MAGIC
+        );
+        let embedded = CompressOptions {
+            embed_dictionary: true,
+            ..options.clone()
+        };
+        assert!(FrameWriter::new(Vec::new(), &embedded).is_err());
+        for offset in [0, 100, 64 * 1024 - 10, 2_000_000] {
+            let mut buf = vec![0u8; 100_000];
+            reader.seek(std::io::SeekFrom::Start(offset)).unwrap();
+            reader.read_exact(&mut buf).unwrap();
+            assert_eq!(buf, data[offset as usize..offset as usize + 100_000]);
+        }
+        assert!(reader.count < seekable.len() as u64 / 2);
+        assert!(
+            SeekableReader::open(std::io::Cursor::new(&stream), Vec::new())
+                .unwrap()
+                .is_none()
+        );
+
+        let mut corrupt = compressed.clone();
+        corrupt[compressed.len() / 2] ^= 0xFF;
+        let mut decoder =
+            ParallelDecoder::new(corrupt.as_slice(), Vec::new(), Default::default()).unwrap();
+        assert!(decoder.read_to_end(&mut Vec::new()).is_err());
+    }
+
+    /// Output that takes a while to accept each write, like a slow disk or network.
+    struct SlowWriter(Vec<u8>);
+
+    impl Write for SlowWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            self.0.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_adapt() {
+        let data = (0..1_000_000u32)
+            .flat_map(|i| (i % 1000).to_le_bytes())
+            .collect::<Vec<_>>();
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap();
+
+        // A slow output leaves time to compress better
+        let options = CompressOptions {
+            compress_level: 1,
+            frame_size: 64 * 1024,
+            adapt: Some((1, 4)),
+            ..Default::default()
+        };
+        let (levels, compressed) = pool.install(|| {
+            let mut writer = FrameWriter::new(SlowWriter(Vec::new()), &options).unwrap();
+            writer.write_all(&data).unwrap();
+            (writer.adapted_levels().unwrap(), writer.finish().unwrap().0)
+        });
+        assert_eq!(levels.first().unwrap().0, 1);
+        assert_eq!(levels.last().unwrap().0, 4);
+        assert_eq!(zstd::decode_all(compressed.as_slice()).unwrap(), data);
+
+        // A fast output waits on compression
+        let options = CompressOptions {
+            compress_level: 19,
+            adapt: Some((-1, 19)),
+            ..options
+        };
+        let levels = pool.install(|| {
+            let mut writer = FrameWriter::new(Vec::new(), &options).unwrap();
+            writer.write_all(&data).unwrap();
+            writer.adapted_levels().unwrap()
+        });
+        assert!(levels.first().unwrap().0 < 19);
+        assert!(levels.iter().all(|(level, _)| *level != 0));
+
+        let options = CompressOptions {
+            adapt: None,
+            ..options
+        };
+        assert!(
+            FrameWriter::new(Vec::new(), &options)
+                .unwrap()
+                .adapted_levels()
+                .is_none()
+        );
+    }
+}
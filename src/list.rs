@@ -0,0 +1,191 @@
+use anyhow::{Error, Result};
+
+use crate::utils;
+
+/// Kind of an archive entry, as shown by the first character of its mode.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Kind {
+    File,
+    Directory,
+    Symlink,
+    HardLink,
+    Other,
+}
+
+impl Kind {
+    fn mode_char(self) -> char {
+        match self {
+            Kind::File | Kind::HardLink => '-',
+            Kind::Directory => 'd',
+            Kind::Symlink => 'l',
+            Kind::Other => '?',
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Kind::File => "file",
+            Kind::Directory => "directory",
+            Kind::Symlink => "symlink",
+            Kind::HardLink => "hardlink",
+            Kind::Other => "other",
+        }
+    }
+}
+
+/// One entry of an archive, as listed by `rpcc l`.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub path: String,
+    pub kind: Kind,
+    /// Permission bits, without the file type
+    pub mode: u32,
+    pub size: u64,
+    /// Modification time in seconds since the Unix epoch
+    pub mtime: i64,
+    /// User and group names, or ids when the names are not stored (only for tar)
+    pub owner: Option<(String, String)>,
+    /// Target of symlinks and hard links
+    pub link: Option<String>,
+    /// Compressed size and compression method (only for zip)
+    pub compression: Option<(u64, String)>,
+}
+
+impl Entry {
+    /// Formats the entry like a line of `tar -tv`, with the compressed size and method of zip
+    /// entries after their size. Times are in UTC.
+    pub fn table_line(&self) -> String {
+        let (year, month, day, hour, minute, _) = utils::civil_from_unix(self.mtime);
+        let mut line = mode_string(self.kind, self.mode);
+        if let Some((user, group)) = &self.owner {
+            line.push_str(&format!(" {}/{}", user, group));
+        }
+        line.push_str(&format!(" {:>12}", self.size));
+        if let Some((compressed_size, method)) = &self.compression {
+            line.push_str(&format!(" {:>12} {:<8}", compressed_size, method));
+        }
+        line.push_str(&format!(
+            " {:04}-{:02}-{:02} {:02}:{:02} {}",
+            year, month, day, hour, minute, self.path
+        ));
+        match (&self.link, self.kind) {
+            (Some(target), Kind::Symlink) => line.push_str(&format!(" -> {}", target)),
+            (Some(target), _) => line.push_str(&format!(" link to {}", target)),
+            (None, _) => {}
+        }
+        line
+    }
+
+    /// Formats the entry as a single line JSON object.
+    pub fn json(&self) -> String {
+        let mut fields = vec![
+            format!("\"path\":{}", json_string(&self.path)),
+            format!("\"type\":\"{}\"", self.kind.name()),
+            format!("\"mode\":\"{:04o}\"", self.mode),
+            format!("\"size\":{}", self.size),
+            format!("\"mtime\":{}", self.mtime),
+        ];
+        if let Some((user, group)) = &self.owner {
+            fields.push(format!("\"user\":{}", json_string(user)));
+            fields.push(format!("\"group\":{}", json_string(group)));
+        }
+        if let Some(target) = &self.link {
+            fields.push(format!("\"link\":{}", json_string(target)));
+        }
+        if let Some((compressed_size, method)) = &self.compression {
+            fields.push(format!("\"compressed_size\":{}", compressed_size));
+            fields.push(format!("\"method\":{}", json_string(method)));
+        }
+        format!("{{{}}}", fields.join(","))
+    }
+}
+
+/// Prints entries to stdout as they are read, one line each.
+pub fn printer(json: bool) -> impl FnMut(Entry) -> Result<(), Error> {
+    move |entry| {
+        use std::io::Write;
+        let line = if json {
+            entry.json()
+        } else {
+            entry.table_line()
+        };
+        match writeln!(std::io::stdout().lock(), "{}", line) {
+            // The reader went away, as with `rpcc l archive | head`
+            Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => std::process::exit(0),
+            result => Ok(result?),
+        }
+    }
+}
+
+/// Renders permission bits like `ls -l`, including setuid, setgid and sticky bits.
+fn mode_string(kind: Kind, mode: u32) -> String {
+    let mut chars = vec![kind.mode_char()];
+    for (shift, special, special_char) in [(6, 0o4000, 's'), (3, 0o2000, 's'), (0, 0o1000, 't')] {
+        let bits = (mode >> shift) & 0o7;
+        chars.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        chars.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        chars.push(match (bits & 0o1 != 0, mode & special != 0) {
+            (true, true) => special_char,
+            (false, true) => special_char.to_ascii_uppercase(),
+            (true, false) => 'x',
+            (false, false) => '-',
+        });
+    }
+    chars.into_iter().collect()
+}
+
+fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry_format() {
+        let mut entry = Entry {
+            path: "bin/run \"1\".sh".to_string(),
+            kind: Kind::File,
+            mode: 0o4755,
+            size: 42,
+            mtime: 1_600_000_000,
+            owner: Some(("root".to_string(), "wheel".to_string())),
+            link: None,
+            compression: None,
+        };
+        assert_eq!(
+            entry.table_line(),
+            "-rwsr-xr-x root/wheel           42 2020-09-13 12:26 bin/run \"1\".sh"
+        );
+        assert_eq!(
+            entry.json(),
+            r#"{"path":"bin/run \"1\".sh","type":"file","mode":"4755","size":42,"mtime":1600000000,"user":"root","group":"wheel"}"#
+        );
+
+        entry.kind = Kind::Symlink;
+        entry.mode = 0o777;
+        entry.owner = None;
+        entry.size = 0;
+        entry.link = Some("target".to_string());
+        entry.compression = Some((0, "Stored".to_string()));
+        assert_eq!(
+            entry.table_line(),
+            "lrwxrwxrwx            0            0 Stored   2020-09-13 12:26 bin/run \"1\".sh -> target"
+        );
+    }
+}
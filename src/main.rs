@@ -1,52 +1,135 @@
 use anyhow::{Context, Error, Result};
 use clap::Parser;
+use std::io::{Read, Seek, Write};
 
 mod args;
+mod codec;
+mod detect;
+mod dict;
+mod estimate;
+mod frames;
+mod list;
+mod owner;
+mod paths;
+mod select;
 mod tests;
 mod utils;
+mod walk;
 mod zip;
 mod zstd;
 
-fn prepare_paths(args: &args::Args) -> Result<(std::path::PathBuf, std::path::PathBuf), Error> {
-    let input = std::path::Path::new(&args.input).to_path_buf();
+fn prepare_paths(
+    args: &args::Args,
+) -> Result<(Vec<std::path::PathBuf>, std::path::PathBuf), Error> {
+    let (inputs, output) = args.inputs_and_output()?;
+    let inputs = inputs
+        .iter()
+        .map(std::path::PathBuf::from)
+        .collect::<Vec<_>>();
+    // Empty with --files-from, where the output is always given
+    let input = inputs.first().cloned().unwrap_or_default();
 
-    if !input.exists() {
-        return Result::Err(Error::msg(format!(
-            "Input path does not exist: {:?}",
-            input
-        )));
+    for input in &inputs {
+        if utils::is_stdio(input) && args.command == args::Command::C {
+            return Result::Err(Error::msg(
+                "Cannot compress from stdin, the input must be a directory",
+            ));
+        }
+        if utils::is_stdio(input) && args.command == args::Command::TrainDict {
+            return Result::Err(Error::msg(
+                "Cannot train on stdin, the input must be a directory",
+            ));
+        }
+        if utils::is_stdio(input) && args.command == args::Command::Estimate {
+            return Result::Err(Error::msg(
+                "Cannot estimate stdin, the input must be a directory",
+            ));
+        }
+
+        if !utils::is_stdio(input) && !input.exists() {
+            return Result::Err(Error::msg(format!(
+                "Input path does not exist: {:?}",
+                input
+            )));
+        }
     }
 
     let msg;
     let output = match args.command {
         args::Command::C => {
             msg = "Compress";
-            let output = match (&args.output, args.compress_type) {
+            let compress_type = args.compress_type.unwrap_or(args::CompressType::TARZSTD);
+            if (args.frame_size.is_some() || args.seekable || args.adapt.is_some())
+                && compress_type != args::CompressType::TARZSTD
+            {
+                return Result::Err(Error::msg(
+                    "--frame-size, --seekable and --adapt only apply to -t tarzstd",
+                ));
+            }
+            if (args.time_budget.is_some() || args.target_size.is_some())
+                && codec::Codec::of(compress_type).is_none()
+            {
+                return Result::Err(Error::msg(
+                    "--time-budget and --target-size only apply to tar archives",
+                ));
+            }
+            args.check_compress_options(compress_type)?;
+            if compress_type == args::CompressType::ZST && inputs.len() != 1 {
+                return Result::Err(Error::msg(
+                    "A zst file holds a single file, use -t tarzstd to compress several inputs",
+                ));
+            }
+            if compress_type == args::CompressType::ZST && !input.is_file() {
+                return Result::Err(Error::msg(format!(
+                    "Input path is not a file: {:?}, use -t tarzstd to compress a directory",
+                    input
+                )));
+            }
+            let output = match (&output, compress_type) {
                 (Some(output), _) => std::path::Path::new(&output).to_path_buf(),
                 (_, args::CompressType::TARZSTD) => input.with_extension("tar.zst"),
+                (_, args::CompressType::TARGZ) => input.with_extension("tar.gz"),
+                (_, args::CompressType::TARXZ) => input.with_extension("tar.xz"),
+                (_, args::CompressType::TARBZ2) => input.with_extension("tar.bz2"),
+                (_, args::CompressType::TARLZ4) => input.with_extension("tar.lz4"),
+                (_, args::CompressType::TAR) => input.with_extension("tar"),
                 (_, args::CompressType::ZIP) => input.with_extension("zip"),
+                (_, args::CompressType::ZST) => {
+                    let mut output = input.clone().into_os_string();
+                    output.push(".zst");
+                    output.into()
+                }
             };
-            if output.exists() {
+            if !utils::is_stdio(&output) && output.exists() {
                 std::fs::remove_file(&output)
                     .with_context(|| format!("Failed to remove file: {:?}", &output))?;
             }
             output
         }
+        args::Command::TrainDict => {
+            msg = "Train";
+            match &output {
+                Some(output) => std::path::Path::new(&output).to_path_buf(),
+                None => input.with_extension("dict"),
+            }
+        }
         args::Command::X => {
             msg = "Decompress";
-            if !input.is_file() {
+            if !utils::is_stdio(&input) && !input.is_file() {
                 return Result::Err(Error::msg(format!("Input path is not a file: {:?}", input)));
             }
-            let output = match (&args.output, args.compress_type) {
-                (Some(output), _) => std::path::Path::new(&output).to_path_buf(),
-                (_, args::CompressType::TARZSTD | args::CompressType::ZIP) => {
-                    input.parent().unwrap().to_path_buf()
-                }
+            let output = match &output {
+                Some(output) => std::path::Path::new(&output).to_path_buf(),
+                None if utils::is_stdio(&input) => std::path::PathBuf::from("."),
+                None => input.parent().unwrap().to_path_buf(),
             };
-            if !output.exists() {
+            // Only a plain zst file can be decompressed to stdout, checked once the type is known
+            if utils::is_stdio(&output) {
+                // Nothing to create
+            } else if !output.exists() {
                 std::fs::create_dir_all(&output)
                     .with_context(|| format!("Failed to create directory: {:?}", &output))?;
-            } else if output.exists() && !output.is_dir() {
+            } else if !output.is_dir() {
                 return Result::Err(Error::msg(format!(
                     "Output path is not a directory: {:?}",
                     output
@@ -54,28 +137,160 @@ fn prepare_paths(args: &args::Args) -> Result<(std::path::PathBuf, std::path::Pa
             }
             output
         }
+        args::Command::Estimate => {
+            if inputs.is_empty() {
+                return Result::Err(Error::msg("Give the directories or files to estimate"));
+            }
+            if args.log_level >= 1 {
+                for input in &inputs {
+                    eprintln!("Estimate from: {:?}", input);
+                }
+            }
+            // Nothing is written, stdout is kept for the table
+            return Ok((inputs, std::path::PathBuf::new()));
+        }
+        args::Command::L | args::Command::T => {
+            if !utils::is_stdio(&input) && !input.is_file() {
+                return Result::Err(Error::msg(format!("Input path is not a file: {:?}", input)));
+            }
+            // Listing and testing have no output, stdout is kept for the entries
+            return Ok((inputs, std::path::PathBuf::new()));
+        }
     };
 
     if args.log_level >= 1 {
-        println!("{} from: {:?}", msg, input);
-        println!("{} to  : {:?}", msg, output);
+        for input in &inputs {
+            eprintln!("{} from: {:?}", msg, input);
+        }
+        if let Some(list) = &args.files_from {
+            eprintln!("{} from: paths listed in {:?}", msg, list);
+        }
+        eprintln!("{} to  : {:?}", msg, output);
     }
 
-    Ok((input, output))
+    Ok((inputs, output))
 }
 
-fn after_compress(start: std::time::Instant, output: &std::path::Path, args: &args::Args) {
-    let elapsed = start.elapsed();
-    let size = if let Ok(meta) = std::fs::metadata(&output) {
-        meta.len()
+/// Opens the output archive, a file or stdout, counting the bytes written to it.
+fn open_output(output: &std::path::Path) -> Result<utils::Counter<Box<dyn Write>>, Error> {
+    let writer: Box<dyn Write> = if utils::is_stdio(output) {
+        Box::new(std::io::BufWriter::new(std::io::stdout().lock()))
     } else {
-        println!("Failed to get metadata for: {:?}", &output);
-        return;
+        Box::new(
+            std::fs::File::create(output)
+                .with_context(|| format!("Failed to create file: {:?}", output))?,
+        )
     };
+    Ok(utils::Counter::new(writer))
+}
 
+/// Opens the input archive, a file or stdin, counting the bytes read from it. The head of
+/// stdin, already read to detect the archive type, is put back in front.
+fn open_input(
+    input: &std::path::Path,
+    stdin_head: Option<Vec<u8>>,
+) -> Result<utils::Counter<Box<dyn Read>>, Error> {
+    let reader: Box<dyn Read> = match stdin_head {
+        Some(head) => Box::new(std::io::Cursor::new(head).chain(std::io::stdin().lock())),
+        None => Box::new(
+            std::fs::File::open(input)
+                .with_context(|| format!("Failed to open file: {:?}", input))?,
+        ),
+    };
+    Ok(utils::Counter::new(reader))
+}
+
+/// Opens the input archive for formats that need to seek, spooling stdin to a temporary file.
+fn open_seekable_input(
+    input: &std::path::Path,
+    stdin_head: Option<Vec<u8>>,
+) -> Result<std::fs::File, Error> {
+    match stdin_head {
+        Some(_) => {
+            let mut spool = tempfile::tempfile().context("Failed to create spool file")?;
+            std::io::copy(&mut open_input(input, stdin_head)?, &mut spool)
+                .context("Failed to spool stdin")?;
+            spool.rewind()?;
+            Ok(spool)
+        }
+        None => {
+            std::fs::File::open(input).with_context(|| format!("Failed to open file: {:?}", input))
+        }
+    }
+}
+
+/// Opens a seekable tarzstd file with a member index, to jump to its members. `None` for other
+/// codecs, stdin and archives without an index, which are read as a stream.
+fn open_member_index(
+    codec: codec::Codec,
+    input: &std::path::Path,
+    stdin_head: &Option<Vec<u8>>,
+    dictionary: Option<&std::path::Path>,
+) -> Result<Option<frames::SeekableReader<std::fs::File>>, Error> {
+    if codec != codec::Codec::Zstd || stdin_head.is_some() {
+        return Ok(None);
+    }
+    let file =
+        std::fs::File::open(input).with_context(|| format!("Failed to open file: {:?}", input))?;
+    frames::SeekableReader::open(file, dict::load(dictionary)?)
+        .with_context(|| format!("Failed to read the seek table of {:?}", input))
+}
+
+/// Fails for archives that can only be extracted to a directory.
+fn check_output_dir(output: &std::path::Path) -> Result<(), Error> {
+    if utils::is_stdio(output) {
+        return Err(Error::msg(
+            "Cannot extract an archive to stdout, the output must be a directory",
+        ));
+    }
+    Ok(())
+}
+
+/// Path a plain zst file is decompressed to: its name without the `.zst` extension, in the
+/// output directory. Stdout when the output is `-`.
+fn zst_output_path(
+    input: &std::path::Path,
+    output: &std::path::Path,
+) -> Result<std::path::PathBuf, Error> {
+    if utils::is_stdio(output) {
+        return Ok(output.to_path_buf());
+    }
+    if utils::is_stdio(input) {
+        return Err(Error::msg(
+            "Cannot name the file decompressed from stdin, use `-` as the output to write it to stdout",
+        ));
+    }
+    match (input.extension(), input.file_stem()) {
+        (Some(extension), Some(stem)) if extension == "zst" || extension == "zstd" => {
+            Ok(output.join(stem))
+        }
+        _ => Err(Error::msg(format!(
+            "Cannot name the file decompressed from {:?}, which has no .zst extension",
+            input
+        ))),
+    }
+}
+
+/// Prints the entries that failed an integrity test and turns them into an error.
+fn check_failures(failures: Vec<Error>, input: &std::path::Path) -> Result<(), Error> {
+    if failures.is_empty() {
+        return Ok(());
+    }
+    for failure in &failures {
+        eprintln!("FAILED: {:#}", failure);
+    }
+    Err(Error::msg(format!(
+        "Found {} corrupt entries in {:?}",
+        failures.len(),
+        input
+    )))
+}
+
+fn after_compress(start: std::time::Instant, size: u64, args: &args::Args) {
+    let elapsed = start.elapsed();
     let speed = size as f64 / elapsed.as_secs_f64();
     if args.log_level >= 1 {
-        println!(
+        eprintln!(
             "Compress end : {:>8}, {:>8}/s, {:>6}",
             utils::readable_bytes(size),
             utils::readable_bytes(speed as u64),
@@ -84,17 +299,11 @@ fn after_compress(start: std::time::Instant, output: &std::path::Path, args: &ar
     }
 }
 
-fn after_decompress(start: std::time::Instant, input: &std::path::Path, args: &args::Args) {
+fn after_decompress(start: std::time::Instant, size: u64, args: &args::Args) {
     let elapsed = start.elapsed();
-    let size = if let Ok(meta) = std::fs::metadata(&input) {
-        meta.len()
-    } else {
-        println!("Failed to get metadata for: {:?}", &input);
-        return;
-    };
     let speed = size as f64 / elapsed.as_secs_f64();
     if args.log_level >= 1 {
-        println!(
+        eprintln!(
             "Decompress end : {:>8}, {:>8}/s, {:>6}",
             utils::readable_bytes(size),
             utils::readable_bytes(speed as u64),
@@ -103,66 +312,309 @@ fn after_decompress(start: std::time::Instant, input: &std::path::Path, args: &a
     }
 }
 
+/// Prints the most file data held in memory at once, with --max-memory.
+fn after_memory(memory: &utils::MemoryBudget, args: &args::Args) {
+    if let (Some(limit), true) = (memory.limit(), args.log_level >= 1) {
+        eprintln!(
+            "Memory peak : {:>8} of {:>8}",
+            utils::readable_bytes(memory.peak()),
+            utils::readable_bytes(limit),
+        );
+    }
+}
+
 fn main() -> Result<()> {
     let args = args::Args::parse();
 
     let start = std::time::Instant::now();
 
-    match (args.command, args.compress_type) {
-        (args::Command::C, args::CompressType::TARZSTD) => {
-            let (input, output) = prepare_paths(&args)?;
-            let mut output_writer = std::fs::File::create(&output)
-                .with_context(|| format!("Failed to create file: {:?}", &output))?;
+    let (inputs, output) = prepare_paths(&args)?;
+    let input = inputs.first().cloned().unwrap_or_default();
 
-            zstd::tar_zstd(
-                &input,
+    // Stdin cannot be reopened, so its head is read once for detection and replayed
+
+    let stdin_head = if args.command != args::Command::C && utils::is_stdio(&input) {
+        let head =
+            detect::read_head(&mut std::io::stdin().lock()).context("Failed to read stdin")?;
+        Some(head)
+    } else {
+        None
+    };
+
+    let compress_type = match (args.command, args.compress_type) {
+        (_, Some(compress_type)) => compress_type,
+        (args::Command::C | args::Command::TrainDict | args::Command::Estimate, None) => {
+            args::CompressType::TARZSTD
+        }
+        (_, None) => {
+            let compress_type = match &stdin_head {
+                Some(head) => detect::detect_head(head, &input)?,
+                None => detect::detect(&input)?,
+            };
+            if args.log_level >= 1 && args.command == args::Command::X {
+                eprintln!("Decompress type: {:?}", compress_type);
+            }
+            compress_type
+        }
+    };
+
+    if args.dict.is_some() && compress_type != args::CompressType::TARZSTD {
+        return Err(Error::msg("--dict only applies to tarzstd archives"));
+    }
+
+    // Tar based types differ only by their codec, the others are zip and plain zst
+    let is_zip = compress_type == args::CompressType::ZIP;
+    match (args.command, codec::Codec::of(compress_type)) {
+        (args::Command::TrainDict, _) => {
+            let sources = walk::sources(&inputs, &args.map)?;
+            let dictionary = dict::train(
+                &sources,
+                &args.compress_options(),
+                args.dict_size,
+                args.log_level,
+            )?;
+            let mut output_writer = open_output(&output)?;
+            output_writer
+                .write_all(&dictionary)
+                .and_then(|_| output_writer.flush())
+                .with_context(|| format!("Failed to write dictionary to: {:?}", output))?;
+        }
+        (args::Command::Estimate, _) => {
+            // Every format unless one is given, with zstd options only applying to zstd
+            let compress_types = match args.compress_type {
+                Some(args::CompressType::ZST) => {
+                    return Err(Error::msg(
+                        "estimate applies to tar archives and zip, a zst file holds a single file",
+                    ));
+                }
+                Some(compress_type) => {
+                    args.check_compress_options(compress_type)?;
+                    vec![compress_type]
+                }
+                None if args.compress_level.is_some() => {
+                    return Err(Error::msg("Give the format of the -l level with -t"));
+                }
+                None => vec![
+                    args::CompressType::TARZSTD,
+                    args::CompressType::TARGZ,
+                    args::CompressType::TARXZ,
+                    args::CompressType::TARBZ2,
+                    args::CompressType::TARLZ4,
+                    args::CompressType::ZIP,
+                ],
+            };
+            let sources = walk::sources(&inputs, &args.map)?;
+            estimate::print_table(
+                &sources,
+                &args.compress_options(),
+                &compress_types,
+                args.compress_level,
+                args.ultra,
+            )
+            .with_context(|| format!("Failed to estimate: {:?}", inputs))?;
+        }
+        (args::Command::C, Some(codec)) => {
+            let sources = walk::sources(&inputs, &args.map)?;
+            let mut options = args.compress_options();
+            if args.time_budget.is_some() || args.target_size.is_some() {
+                let budget = estimate::Budget {
+                    time: args.time_budget,
+                    size: args.target_size,
+                };
+                options.compress_level = estimate::pick_level(
+                    codec,
+                    &sources,
+                    &options,
+                    args.ultra,
+                    budget,
+                    args.log_level,
+                )?
+                .level;
+            }
+            let mut output_writer = open_output(&output)?;
+
+            zstd::tar(
+                codec,
+                &sources,
                 &mut output_writer,
-                args.compress_level.unwrap_or(3),
-                args.no_long_distance_matching,
-                args.small_file_size,
+                &options,
                 args.log_level,
             )
+            .and_then(|_| Ok(output_writer.flush()?))
             .with_context(|| {
                 format!(
-                    "Failed to create tar zstd from: {:?} to: {:?}",
-                    input, output
+                    "Failed to create tar {} from: {:?} to: {:?}",
+                    codec.name(),
+                    inputs,
+                    output
                 )
             })?;
 
-            after_compress(start, &output, &args);
+            after_compress(start, output_writer.count, &args);
+            after_memory(&options.memory, &args);
         }
-        (args::Command::X, args::CompressType::TARZSTD) => {
-            let (input, output) = prepare_paths(&args)?;
-            let mut input_reader = std::fs::File::open(&input)
-                .with_context(|| format!("Failed to open file: {:?}", &input))?;
-            zstd::untar_zstd(&mut input_reader, &output, args.log_level).with_context(|| {
-                format!(
-                    "Failed to decompress tar zstd from: {:?} to: {:?}",
-                    input, output
-                )
-            })?;
+        (args::Command::X, Some(codec)) => {
+            check_output_dir(&output)?;
+            let options = args.extract_options();
 
-            after_decompress(start, &input, &args);
+            // Selected members are read through the member index when there is one
+            let member_index = match args.patterns.is_empty() {
+                true => None,
+                false => open_member_index(codec, &input, &stdin_head, args.dict.as_deref())?,
+            };
+            if let Some(mut reader) = member_index {
+                zstd::untar_members(&mut reader, &output, &options, args.log_level).with_context(
+                    || {
+                        format!(
+                            "Failed to decompress tar {} from: {:?} to: {:?}",
+                            codec.name(),
+                            input,
+                            output
+                        )
+                    },
+                )?;
+                after_decompress(start, reader.count, &args);
+                after_memory(&options.memory, &args);
+                return Ok(());
+            }
+
+            let mut input_reader = open_input(&input, stdin_head)?;
+            zstd::untar(codec, &mut input_reader, &output, &options, args.log_level).with_context(
+                || {
+                    format!(
+                        "Failed to decompress tar {} from: {:?} to: {:?}",
+                        codec.name(),
+                        input,
+                        output
+                    )
+                },
+            )?;
+
+            after_decompress(start, input_reader.count, &args);
+            after_memory(&options.memory, &args);
         }
-        (args::Command::C, args::CompressType::ZIP) => {
-            let (input, output) = prepare_paths(&args)?;
-            let mut output_writer = std::fs::File::create(&output)
-                .with_context(|| format!("Failed to create file: {:?}", &output))?;
-            zip::zip(&input, &mut output_writer, args.log_level).unwrap();
-            after_compress(start, &output, &args);
+        (args::Command::C, None) if is_zip => {
+            let sources = walk::sources(&inputs, &args.map)?;
+            let options = args.compress_options();
+            if utils::is_stdio(&output) {
+                let mut output_writer = open_output(&output)?;
+                zip::zip_stream(&sources, &mut output_writer, &options, args.log_level)
+                    .and_then(|_| Ok(output_writer.flush()?))
+                    .with_context(|| {
+                        format!("Failed to create zip from: {:?} to: {:?}", inputs, output)
+                    })?;
+                after_compress(start, output_writer.count, &args);
+            } else {
+                let mut output_writer = std::fs::File::create(&output)
+                    .with_context(|| format!("Failed to create file: {:?}", &output))?;
+                zip::zip(&sources, &mut output_writer, &options, args.log_level).with_context(
+                    || format!("Failed to create zip from: {:?} to: {:?}", inputs, output),
+                )?;
+                after_compress(start, output_writer.stream_position()?, &args);
+            }
+            after_memory(&options.memory, &args);
         }
-        (args::Command::X, args::CompressType::ZIP) => {
-            let (input, output) = prepare_paths(&args)?;
-            let mut input_reader = std::fs::File::open(&input)
-                .with_context(|| format!("Failed to open file: {:?}", &input))?;
-            zip::unzip(&mut input_reader, &output, args.log_level).with_context(|| {
-                format!(
-                    "Failed to decompress zip from: {:?} to: {:?}",
-                    input, output
-                )
-            })?;
+        (args::Command::X, None) if is_zip => {
+            check_output_dir(&output)?;
+            // Zip needs to seek to its central directory
+            let mut input_reader = open_seekable_input(&input, stdin_head)?;
+            let options = args.extract_options();
+            zip::unzip(&mut input_reader, &output, &options, args.log_level).with_context(
+                || {
+                    format!(
+                        "Failed to decompress zip from: {:?} to: {:?}",
+                        input, output
+                    )
+                },
+            )?;
+
+            after_decompress(start, input_reader.metadata()?.len(), &args);
+            after_memory(&options.memory, &args);
+        }
+        (args::Command::L, Some(codec)) => {
+            if let Some(mut reader) =
+                open_member_index(codec, &input, &stdin_head, args.dict.as_deref())?
+            {
+                zstd::list_members(&mut reader, list::printer(args.json))
+                    .with_context(|| format!("Failed to list tar {}: {:?}", codec.name(), input))?;
+                return Ok(());
+            }
+            let mut input_reader = open_input(&input, stdin_head)?;
+            zstd::list_tar(
+                codec,
+                &mut input_reader,
+                args.dict.as_deref(),
+                list::printer(args.json),
+            )
+            .with_context(|| format!("Failed to list tar {}: {:?}", codec.name(), input))?;
+        }
+        (args::Command::T, Some(codec)) => {
+            let mut input_reader = open_input(&input, stdin_head)?;
+            let failures = zstd::verify_tar(
+                codec,
+                &mut input_reader,
+                &args.extract_options(),
+                args.log_level,
+            )
+            .with_context(|| format!("Failed to test tar {}: {:?}", codec.name(), input))?;
+            check_failures(failures, &input)?;
+
+            after_decompress(start, input_reader.count, &args);
+        }
+        (args::Command::T, None) if is_zip => {
+            let mut input_reader = open_seekable_input(&input, stdin_head)?;
+            let failures = zip::verify_zip(&mut input_reader, args.log_level)
+                .with_context(|| format!("Failed to test zip: {:?}", input))?;
+            check_failures(failures, &input)?;
+
+            after_decompress(start, input_reader.metadata()?.len(), &args);
+        }
+        (args::Command::L, None) if is_zip => {
+            let mut input_reader = open_seekable_input(&input, stdin_head)?;
+            zip::list_zip(&mut input_reader, list::printer(args.json))
+                .with_context(|| format!("Failed to list zip: {:?}", input))?;
+        }
+        (args::Command::C, None) => {
+            let mut output_writer = open_output(&output)?;
+            zstd::compress_zst(
+                &input,
+                &mut output_writer,
+                &args.compress_options(),
+                args.log_level,
+            )
+            .and_then(|_| Ok(output_writer.flush()?))
+            .with_context(|| format!("Failed to create zstd from: {:?} to: {:?}", input, output))?;
+
+            after_compress(start, output_writer.count, &args);
+        }
+        (args::Command::X, None) => {
+            let output = zst_output_path(&input, &output)?;
+            let mut input_reader = open_input(&input, stdin_head)?;
+            let mut output_writer = open_output(&output)?;
+            zstd::decompress_zst(&mut input_reader, &mut output_writer)
+                .and_then(|_| Ok(output_writer.flush()?))
+                .with_context(|| {
+                    format!(
+                        "Failed to decompress zstd from: {:?} to: {:?}",
+                        input, output
+                    )
+                })?;
+
+            after_decompress(start, input_reader.count, &args);
+        }
+        (args::Command::L, None) => {
+            return Err(Error::msg(format!(
+                "Cannot list {:?}, a plain zstd file holds a single file without names",
+                input
+            )));
+        }
+        (args::Command::T, None) => {
+            let mut input_reader = open_input(&input, stdin_head)?;
+            let failures = zstd::verify_zst(&mut input_reader)
+                .with_context(|| format!("Failed to test zstd: {:?}", input))?;
+            check_failures(failures, &input)?;
 
-            after_decompress(start, &input, &args);
+            after_decompress(start, input_reader.count, &args);
         }
     }
 
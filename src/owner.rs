@@ -0,0 +1,150 @@
+//! User and group lookups used to store and restore file ownership.
+//!
+//! Lookups go through the C library so they honour NSS, and are cached because the same few
+//! owners repeat across millions of entries. On platforms without Unix ownership every lookup
+//! comes back empty.
+
+#[cfg(unix)]
+mod imp {
+    use std::collections::HashMap;
+    use std::ffi::{CStr, CString};
+    use std::hash::Hash;
+    use std::sync::{Mutex, OnceLock};
+
+    type Cache<K, V> = OnceLock<Mutex<HashMap<K, Option<V>>>>;
+
+    static USER_NAMES: Cache<u32, String> = OnceLock::new();
+    static GROUP_NAMES: Cache<u32, String> = OnceLock::new();
+    static USER_IDS: Cache<String, u32> = OnceLock::new();
+    static GROUP_IDS: Cache<String, u32> = OnceLock::new();
+
+    fn cached<K: Eq + Hash + Clone, V: Clone>(
+        cache: &Cache<K, V>,
+        key: &K,
+        lookup: impl FnOnce() -> Option<V>,
+    ) -> Option<V> {
+        let cache = cache.get_or_init(Default::default);
+        if let Some(value) = cache.lock().unwrap().get(key) {
+            return value.clone();
+        }
+        let value = lookup();
+        cache.lock().unwrap().insert(key.clone(), value.clone());
+        value
+    }
+
+    /// Calls a reentrant `getpw*_r`/`getgr*_r` style function, growing the buffer on `ERANGE`.
+    fn lookup<T>(
+        mut call: impl FnMut(*mut T, *mut libc::c_char, usize, *mut *mut T) -> libc::c_int,
+    ) -> Option<T> {
+        let mut buf = vec![0 as libc::c_char; 1024];
+        loop {
+            let mut record = std::mem::MaybeUninit::<T>::uninit();
+            let mut result = std::ptr::null_mut();
+            let ret = call(
+                record.as_mut_ptr(),
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            );
+            if ret == libc::ERANGE && buf.len() < 1 << 20 {
+                buf.resize(buf.len() * 2, 0);
+                continue;
+            }
+            if ret != 0 || result.is_null() {
+                return None;
+            }
+            // SAFETY: the call succeeded and pointed `result` at `record`.
+            return Some(unsafe { record.assume_init() });
+        }
+    }
+
+    fn to_string(name: *const libc::c_char) -> Option<String> {
+        // SAFETY: the C library returns NUL terminated names that live in our buffer.
+        unsafe { CStr::from_ptr(name) }
+            .to_str()
+            .ok()
+            .map(str::to_string)
+    }
+
+    pub fn is_root() -> bool {
+        // SAFETY: geteuid has no preconditions.
+        unsafe { libc::geteuid() == 0 }
+    }
+
+    pub fn user_name(uid: u32) -> Option<String> {
+        cached(&USER_NAMES, &uid, || {
+            let mut name = None;
+            lookup::<libc::passwd>(|pwd, buf, len, result| {
+                // SAFETY: all pointers are valid for the duration of the call.
+                let ret = unsafe { libc::getpwuid_r(uid, pwd, buf, len, result) };
+                if ret == 0 && !result.is_null() {
+                    name = to_string(unsafe { (*pwd).pw_name });
+                }
+                ret
+            })?;
+            name
+        })
+    }
+
+    pub fn group_name(gid: u32) -> Option<String> {
+        cached(&GROUP_NAMES, &gid, || {
+            let mut name = None;
+            lookup::<libc::group>(|grp, buf, len, result| {
+                // SAFETY: all pointers are valid for the duration of the call.
+                let ret = unsafe { libc::getgrgid_r(gid, grp, buf, len, result) };
+                if ret == 0 && !result.is_null() {
+                    name = to_string(unsafe { (*grp).gr_name });
+                }
+                ret
+            })?;
+            name
+        })
+    }
+
+    pub fn user_id(name: &str) -> Option<u32> {
+        cached(&USER_IDS, &name.to_string(), || {
+            let c_name = CString::new(name).ok()?;
+            let pwd = lookup::<libc::passwd>(|pwd, buf, len, result| {
+                // SAFETY: all pointers are valid for the duration of the call.
+                unsafe { libc::getpwnam_r(c_name.as_ptr(), pwd, buf, len, result) }
+            })?;
+            Some(pwd.pw_uid)
+        })
+    }
+
+    pub fn group_id(name: &str) -> Option<u32> {
+        cached(&GROUP_IDS, &name.to_string(), || {
+            let c_name = CString::new(name).ok()?;
+            let grp = lookup::<libc::group>(|grp, buf, len, result| {
+                // SAFETY: all pointers are valid for the duration of the call.
+                unsafe { libc::getgrnam_r(c_name.as_ptr(), grp, buf, len, result) }
+            })?;
+            Some(grp.gr_gid)
+        })
+    }
+}
+
+#[cfg(not(unix))]
+mod imp {
+    pub fn is_root() -> bool {
+        false
+    }
+
+    pub fn user_name(_uid: u32) -> Option<String> {
+        None
+    }
+
+    pub fn group_name(_gid: u32) -> Option<String> {
+        None
+    }
+
+    pub fn user_id(_name: &str) -> Option<u32> {
+        None
+    }
+
+    pub fn group_id(_name: &str) -> Option<u32> {
+        None
+    }
+}
+
+pub use imp::*;
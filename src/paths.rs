@@ -0,0 +1,125 @@
+use anyhow::{Error, Result};
+use std::collections::HashSet;
+use std::path::{Component, Path, PathBuf};
+
+const UNSAFE_HINT: &str = "use --allow-unsafe-paths to extract it anyway";
+
+/// Normalizes an archive entry name into a path relative to the destination directory.
+///
+/// `.` components are dropped and `..` components are resolved lexically. Absolute names and
+/// names that climb above the destination are rejected unless `allow_unsafe` is set, in which
+/// case the name is returned untouched.
+pub fn entry_path(name: &Path, allow_unsafe: bool) -> Result<PathBuf, Error> {
+    if allow_unsafe {
+        return Ok(name.to_path_buf());
+    }
+
+    let mut normalized = PathBuf::new();
+    for component in name.components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => {
+                return Err(Error::msg(format!(
+                    "Refusing to extract {:?}: absolute path ({})",
+                    name, UNSAFE_HINT
+                )));
+            }
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    return Err(Error::msg(format!(
+                        "Refusing to extract {:?}: path escapes the destination directory ({})",
+                        name, UNSAFE_HINT
+                    )));
+                }
+            }
+            Component::Normal(part) => normalized.push(part),
+        }
+    }
+    Ok(normalized)
+}
+
+/// Checks that a symlink stored at `rel_path` with the given `target` stays inside the
+/// destination directory.
+pub fn check_link_target(rel_path: &Path, target: &Path, allow_unsafe: bool) -> Result<(), Error> {
+    if allow_unsafe {
+        return Ok(());
+    }
+
+    if target.has_root() {
+        return Err(Error::msg(format!(
+            "Refusing to extract symlink {:?} -> {:?}: absolute link target ({})",
+            rel_path, target, UNSAFE_HINT
+        )));
+    }
+
+    let resolved = rel_path.parent().unwrap_or(Path::new("")).join(target);
+    entry_path(&resolved, false).map_err(|_| {
+        Error::msg(format!(
+            "Refusing to extract symlink {:?} -> {:?}: link target escapes the destination directory ({})",
+            rel_path, target, UNSAFE_HINT
+        ))
+    })?;
+    Ok(())
+}
+
+/// Checks that no parent directory of `rel_path` is one of the `symlinks` created so far by the
+/// extraction, which would have it written wherever the symlink points.
+pub fn check_parents(
+    rel_path: &Path,
+    symlinks: &HashSet<PathBuf>,
+    allow_unsafe: bool,
+) -> Result<(), Error> {
+    if allow_unsafe {
+        return Ok(());
+    }
+
+    match rel_path
+        .ancestors()
+        .skip(1)
+        .find(|parent| symlinks.contains(*parent))
+    {
+        Some(symlink) => Err(Error::msg(format!(
+            "Refusing to extract {:?}: path goes through the extracted symlink {:?} ({})",
+            rel_path, symlink, UNSAFE_HINT
+        ))),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry_path() {
+        assert_eq!(
+            entry_path(Path::new("./a/b/../c.txt"), false).unwrap(),
+            PathBuf::from("a/c.txt")
+        );
+        assert!(entry_path(Path::new("../../etc/cron.d/x"), false).is_err());
+        assert!(entry_path(Path::new("a/../../x"), false).is_err());
+        assert!(entry_path(Path::new("/etc/passwd"), false).is_err());
+        assert_eq!(
+            entry_path(Path::new("../x"), true).unwrap(),
+            PathBuf::from("../x")
+        );
+    }
+
+    #[test]
+    fn test_check_link_target() {
+        assert!(check_link_target(Path::new("a/link"), Path::new("../b.txt"), false).is_ok());
+        assert!(check_link_target(Path::new("a/link"), Path::new("../../b.txt"), false).is_err());
+        assert!(check_link_target(Path::new("link"), Path::new("/etc/passwd"), false).is_err());
+        assert!(check_link_target(Path::new("link"), Path::new("/etc/passwd"), true).is_ok());
+    }
+
+    #[test]
+    fn test_check_parents() {
+        let symlinks = HashSet::from([PathBuf::from("d/l")]);
+        assert!(check_parents(Path::new("d/l"), &symlinks, false).is_ok());
+        assert!(check_parents(Path::new("d/link"), &symlinks, false).is_ok());
+        assert!(check_parents(Path::new("d/l/x"), &symlinks, false).is_err());
+        assert!(check_parents(Path::new("d/l/x/y"), &symlinks, false).is_err());
+        assert!(check_parents(Path::new("d/l/x"), &symlinks, true).is_ok());
+    }
+}
@@ -0,0 +1,107 @@
+use anyhow::{Context, Error, Result};
+
+/// Archive members selected for extraction on the command line, by path or glob pattern.
+///
+/// A pattern selects the entries it matches and everything below them, so `config` selects
+/// `config/app.toml`. `*` does not match across `/`. No patterns select every entry.
+pub struct Selection {
+    patterns: Vec<String>,
+    set: globset::GlobSet,
+    matched: Vec<bool>,
+}
+
+impl Selection {
+    pub fn new(patterns: &[String]) -> Result<Self, Error> {
+        let patterns = patterns
+            .iter()
+            .map(|pattern| {
+                let pattern = pattern.trim_start_matches("./").trim_end_matches('/');
+                pattern.to_string()
+            })
+            .collect::<Vec<_>>();
+
+        let mut builder = globset::GlobSetBuilder::new();
+        for pattern in &patterns {
+            let glob = globset::GlobBuilder::new(pattern)
+                .literal_separator(true)
+                .build()
+                .with_context(|| format!("Invalid pattern {:?}", pattern))?;
+            builder.add(glob);
+        }
+
+        Ok(Self {
+            matched: vec![false; patterns.len()],
+            set: builder.build()?,
+            patterns,
+        })
+    }
+
+    /// Selects every entry.
+    pub fn all() -> Self {
+        Self {
+            patterns: Vec::new(),
+            set: globset::GlobSet::empty(),
+            matched: Vec::new(),
+        }
+    }
+
+    /// Whether the entry at `rel_path`, or a directory containing it, is selected.
+    pub fn matches(&mut self, rel_path: &std::path::Path) -> bool {
+        if self.patterns.is_empty() {
+            return true;
+        }
+        for ancestor in rel_path.ancestors() {
+            if ancestor.as_os_str().is_empty() {
+                break;
+            }
+            let indices = self.set.matches(ancestor);
+            if !indices.is_empty() {
+                for index in indices {
+                    self.matched[index] = true;
+                }
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Fails for patterns that did not match any entry, like `tar` does.
+    pub fn check_unmatched(&self) -> Result<(), Error> {
+        let unmatched = self
+            .patterns
+            .iter()
+            .zip(&self.matched)
+            .filter(|(_, matched)| !**matched)
+            .map(|(pattern, _)| pattern)
+            .collect::<Vec<_>>();
+        if !unmatched.is_empty() {
+            return Err(Error::msg(format!("Not found in archive: {:?}", unmatched)));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn test_selection() {
+        let patterns = ["logs/2026-*/*.json", "./config/", "missing"].map(String::from);
+        let mut selection = Selection::new(&patterns).unwrap();
+
+        assert!(selection.matches(Path::new("logs/2026-01/a.json")));
+        assert!(!selection.matches(Path::new("logs/2026-01/deep/a.json")));
+        assert!(!selection.matches(Path::new("logs/2025-01/a.json")));
+        assert!(selection.matches(Path::new("config")));
+        assert!(selection.matches(Path::new("config/app.toml")));
+        assert!(!selection.matches(Path::new("configs/app.toml")));
+
+        let err = selection.check_unmatched().unwrap_err();
+        assert!(err.to_string().contains("missing"));
+
+        assert!(Selection::all().matches(Path::new("anything")));
+        assert!(Selection::new(&["[".to_string()]).is_err());
+    }
+}
@@ -1,10 +1,11 @@
 #[cfg(test)]
+#[allow(clippy::module_inception)]
 pub mod tests {
     use anyhow::{Error, Result};
     use std::collections::HashMap;
     use std::path::Path;
 
-    fn calculate_hash(dir: &Path) -> Result<HashMap<String, String>, Error> {
+    pub fn calculate_hash(dir: &Path) -> Result<HashMap<String, String>, Error> {
         let result = walkdir::WalkDir::new(dir)
             .into_iter()
             .filter_map(|entry| {
@@ -30,6 +31,25 @@ pub mod tests {
         Ok(result)
     }
 
+    fn collect_dirs(dir: &Path) -> Result<HashMap<String, i64>, Error> {
+        let result = walkdir::WalkDir::new(dir)
+            .min_depth(1)
+            .into_iter()
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                if entry.file_type().is_dir() {
+                    let metadata = entry.metadata().ok()?;
+                    let mtime = filetime::FileTime::from_last_modification_time(&metadata);
+                    let rel_path = entry.path().strip_prefix(dir).ok()?;
+                    Some((rel_path.to_string_lossy().to_string(), mtime.unix_seconds()))
+                } else {
+                    None
+                }
+            })
+            .collect::<HashMap<_, _>>();
+        Ok(result)
+    }
+
     fn prepare_compress_test_data() -> (tempfile::TempDir, tempfile::TempDir) {
         // Create temporary directory
 
@@ -62,6 +82,16 @@ pub mod tests {
         }
         std::fs::write(&test_big, &data[..size_mb * 1024 * 1024]).unwrap();
 
+        let test_empty = src_dir.path().join("dir/empty");
+        std::fs::create_dir_all(&test_empty).unwrap();
+
+        // Even seconds, zip stores times with a 2 second resolution
+
+        for dir in [test_empty, src_dir.path().join("dir")] {
+            filetime::set_file_mtime(&dir, filetime::FileTime::from_unix_time(1_600_000_000, 0))
+                .unwrap();
+        }
+
         (src_dir, dest_dir)
     }
 
@@ -69,13 +99,15 @@ pub mod tests {
         pub src_dir: tempfile::TempDir,
         pub dest_dir: tempfile::TempDir,
         pub before_hash: HashMap<String, String>,
+        pub before_dirs: HashMap<String, i64>,
         pub intermediate: std::io::Cursor<Vec<u8>>,
     }
 
     impl Tester {
         pub fn new() -> Self {
             let (src_dir, dest_dir) = prepare_compress_test_data();
-            let before_hash = calculate_hash(&src_dir.path()).unwrap();
+            let before_hash = calculate_hash(src_dir.path()).unwrap();
+            let before_dirs = collect_dirs(src_dir.path()).unwrap();
 
             let buf = Vec::new();
             let intermediate = std::io::Cursor::new(buf);
@@ -84,15 +116,23 @@ pub mod tests {
                 src_dir,
                 dest_dir,
                 before_hash,
+                before_dirs,
                 intermediate,
             }
         }
 
         pub fn assert(&self) {
-            let after_hash = calculate_hash(&self.dest_dir.path()).unwrap();
+            let after_hash = calculate_hash(self.dest_dir.path()).unwrap();
             assert!(self.src_dir.path().exists());
             assert!(self.dest_dir.path().exists());
             assert_eq!(self.before_hash, after_hash);
+            let after_dirs = collect_dirs(self.dest_dir.path()).unwrap();
+            assert_eq!(self.before_dirs, after_dirs);
+        }
+
+        /// The source directory, archived by its contents.
+        pub fn sources(&self) -> Vec<(std::path::PathBuf, std::path::PathBuf)> {
+            vec![(self.src_dir.path().to_path_buf(), std::path::PathBuf::new())]
         }
 
         pub fn flush_intermediate(&mut self) {
@@ -1,12 +1,12 @@
-use anyhow::{Error, Result};
+use anyhow::{Context, Error, Result};
 
 pub fn readable_bytes(bytes: u64) -> String {
     const UNITS: [&str; 6] = ["B", "KB", "MB", "GB", "TB", "PB"];
 
     let mut num = bytes as f64;
-    for i in 0..(UNITS.len() - 1) {
+    for unit in &UNITS[..UNITS.len() - 1] {
         if num < 1000.0 {
-            return format!("{:.2}{}", num, UNITS[i]);
+            return format!("{:.2}{}", num, unit);
         }
         num /= 1024.0;
     }
@@ -26,6 +26,250 @@ pub fn readable_elapse(seconds: f64) -> String {
     }
     format!("{:.2}{}", num, UNITS[UNITS.len() - 1])
 }
+
+/// Whether a path given on the command line stands for stdin or stdout.
+pub fn is_stdio(path: &std::path::Path) -> bool {
+    path.as_os_str() == "-"
+}
+
+/// Counts the bytes read from or written to the wrapped reader or writer.
+pub struct Counter<T> {
+    pub inner: T,
+    pub count: u64,
+}
+
+impl<T> Counter<T> {
+    pub fn new(inner: T) -> Self {
+        Self { inner, count: 0 }
+    }
+}
+
+impl<T: std::io::Read> std::io::Read for Counter<T> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+}
+
+impl<T: std::io::Write> std::io::Write for Counter<T> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Sets the modification time of extracted directories, given as seconds since the Unix epoch.
+pub fn set_dir_mtimes(directories: &[(std::path::PathBuf, u64)]) -> Result<(), Error> {
+    for (path, mtime) in directories {
+        filetime::set_file_mtime(path, filetime::FileTime::from_unix_time(*mtime as i64, 0))
+            .with_context(|| format!("Failed to set modification time of {:?}", path))?;
+    }
+    Ok(())
+}
+
+/// Splits seconds since the Unix epoch into a UTC (year, month, day, hour, minute, second).
+pub fn civil_from_unix(secs: i64) -> (i64, u32, u32, u32, u32, u32) {
+    let days = secs.div_euclid(86400);
+    let rem = secs.rem_euclid(86400);
+
+    // Howard Hinnant's days-to-civil algorithm
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (
+        year,
+        month,
+        day,
+        (rem / 3600) as u32,
+        (rem % 3600 / 60) as u32,
+        (rem % 60) as u32,
+    )
+}
+
+/// Inverse of [`civil_from_unix`].
+pub fn unix_from_civil(
+    year: i64,
+    month: u32,
+    day: u32,
+    hour: u32,
+    minute: u32,
+    second: u32,
+) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+    days * 86400 + hour as i64 * 3600 + minute as i64 * 60 + second as i64
+}
+
+/// Releases items tagged with their position in the walk, either as they arrive or, when
+/// `ordered`, strictly by position, holding back the ones that arrive early.
+///
+/// Every position has to be pushed once; `None` marks a position that produced no item.
+pub struct Reorder<T> {
+    ordered: bool,
+    next: usize,
+    pending: std::collections::BTreeMap<usize, Option<T>>,
+}
+
+impl<T> Reorder<T> {
+    pub fn new(ordered: bool) -> Self {
+        Self {
+            ordered,
+            next: 0,
+            pending: std::collections::BTreeMap::new(),
+        }
+    }
+
+    /// Returns the items that are ready to be written after `item` arrived at `index`.
+    pub fn push(&mut self, index: usize, item: Option<T>) -> Vec<T> {
+        if !self.ordered {
+            return item.into_iter().collect();
+        }
+        self.pending.insert(index, item);
+        let mut ready = Vec::new();
+        while let Some(item) = self.pending.remove(&self.next) {
+            ready.extend(item);
+            self.next += 1;
+        }
+        ready
+    }
+
+    /// Returns the items still held back, in order. Senders push every position, entries that
+    /// failed to read as `None`, so items are only left here when a sender stopped early.
+    pub fn finish(self) -> Vec<T> {
+        self.pending.into_values().flatten().collect()
+    }
+}
+
+/// Limit on the bytes of file data buffered in memory at once, shared by the threads reading
+/// and writing the buffers and by every clone. Without a limit, reserving never waits.
+#[derive(Debug, Clone)]
+pub struct MemoryBudget {
+    limit: Option<u64>,
+    /// Bytes reserved, and a signal when some are released
+    shared: Option<std::sync::Arc<(std::sync::Mutex<Usage>, std::sync::Condvar)>>,
+}
+
+#[derive(Debug, Default)]
+struct Usage {
+    reserved: u64,
+    /// Most bytes reserved at once
+    peak: u64,
+}
+
+/// Bytes reserved from a [`MemoryBudget`], released when dropped along with the buffer.
+pub struct Reservation {
+    bytes: u64,
+    shared: Option<std::sync::Arc<(std::sync::Mutex<Usage>, std::sync::Condvar)>>,
+}
+
+impl MemoryBudget {
+    pub fn new(limit: Option<u64>) -> Self {
+        Self {
+            limit,
+            shared: limit.map(|_| Default::default()),
+        }
+    }
+
+    pub fn limit(&self) -> Option<u64> {
+        self.limit
+    }
+
+    /// Whether `bytes` are more than the limit, so they are streamed in pieces instead of
+    /// being buffered whole.
+    pub fn exceeds(&self, bytes: u64) -> bool {
+        self.limit.is_some_and(|limit| bytes > limit)
+    }
+
+    /// Most bytes reserved at once so far, 0 without a limit.
+    pub fn peak(&self) -> u64 {
+        self.shared
+            .as_ref()
+            .map_or(0, |shared| shared.0.lock().unwrap().peak)
+    }
+
+    /// Reserves `bytes`, waiting until they fit in the limit. More bytes than the limit only
+    /// wait until nothing else is reserved, so every buffer gets through.
+    pub fn reserve(&self, bytes: u64) -> Reservation {
+        self.acquire(bytes, Wait::UntilFits).unwrap()
+    }
+
+    /// Reserves `bytes` when they fit in the limit now, without waiting.
+    pub fn try_reserve(&self, bytes: u64) -> Option<Reservation> {
+        self.acquire(bytes, Wait::Never)
+    }
+
+    /// Reserves `bytes` right away, even past the limit, for a buffer that cannot wait for
+    /// the others to be released, as they are released by the thread that needs it.
+    pub fn reserve_now(&self, bytes: u64) -> Reservation {
+        self.acquire(bytes, Wait::Forced).unwrap()
+    }
+
+    fn acquire(&self, bytes: u64, wait: Wait) -> Option<Reservation> {
+        let (Some(shared), Some(limit)) = (&self.shared, self.limit) else {
+            return Some(Reservation {
+                bytes: 0,
+                shared: None,
+            });
+        };
+        let (usage, released) = &**shared;
+        let mut usage = usage.lock().unwrap();
+        while usage.reserved > 0 && usage.reserved + bytes > limit {
+            match wait {
+                Wait::UntilFits => usage = released.wait(usage).unwrap(),
+                Wait::Never => return None,
+                Wait::Forced => break,
+            }
+        }
+        usage.reserved += bytes;
+        usage.peak = usage.peak.max(usage.reserved);
+        Some(Reservation {
+            bytes,
+            shared: Some(shared.clone()),
+        })
+    }
+}
+
+/// How [`MemoryBudget::acquire`] handles bytes that do not fit in the limit.
+enum Wait {
+    UntilFits,
+    Never,
+    Forced,
+}
+
+impl Default for MemoryBudget {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if let Some(shared) = &self.shared {
+            let (usage, released) = &**shared;
+            usage.lock().unwrap().reserved -= self.bytes;
+            released.notify_all();
+        }
+    }
+}
+
 pub enum ProgressData {
     Data((String, u64)),
     Print,
@@ -53,7 +297,7 @@ impl Progress {
                         let interval = if first { 1 } else { 5 };
                         first = false;
                         std::thread::sleep(std::time::Duration::from_secs(interval));
-                        if let Err(_) = tx_clone.send(ProgressData::Print) {
+                        if tx_clone.send(ProgressData::Print).is_err() {
                             break;
                         }
                     }
@@ -66,7 +310,7 @@ impl Progress {
                         size += raw_size;
                         num_files += 1;
                         if log_level >= 3 {
-                            println!(
+                            eprintln!(
                                 "{}, {:?}",
                                 Self::compress_message(&prefix, start, size, num_files),
                                 rel_path
@@ -74,14 +318,14 @@ impl Progress {
                         }
                     }
                     ProgressData::Print => {
-                        println!(
+                        eprintln!(
                             "{}",
                             Self::compress_message(&prefix, start, size, num_files)
                         );
                     }
                     ProgressData::Stop => {
                         if log_level == 2 {
-                            println!(
+                            eprintln!(
                                 "{}",
                                 Self::compress_message(&prefix, start, size, num_files)
                             );
@@ -0,0 +1,661 @@
+use anyhow::{Context, Error, Result};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::args::CompressOptions;
+use crate::utils;
+
+/// Version control directories dropped by `--exclude-vcs`.
+const VCS_DIRS: [&str; 3] = [".git", ".hg", ".svn"];
+
+/// Name and signature of the cache directory tag, see https://bford.info/cachedir/
+const CACHEDIR_TAG: &str = "CACHEDIR.TAG";
+const CACHEDIR_SIGNATURE: &[u8] = b"Signature: 8a477f597d28d172789f06886806bc55";
+
+/// Walks `src_dir` in the order entries are archived, without the entries filtered out by the
+/// options. Excluded directories are pruned, so nothing below them is read.
+pub fn walk(
+    src_dir: &Path,
+    options: &CompressOptions,
+) -> Result<impl Iterator<Item = walkdir::Result<walkdir::DirEntry>> + use<>, Error> {
+    let mut walk = walkdir::WalkDir::new(src_dir).follow_links(options.follow_symlinks);
+    if options.reproducible {
+        walk = walk.sort_by_file_name();
+    }
+    let mut filter = Filter::new(src_dir, options)?;
+    let include = filter.include.take();
+    let root = src_dir.to_path_buf();
+
+    Ok(walk
+        .into_iter()
+        .filter_entry(move |entry| filter.keep(entry))
+        .filter(move |entry| match (entry, &include) {
+            (Ok(entry), Some(include)) => entry
+                .path()
+                .strip_prefix(&root)
+                .is_ok_and(|rel_path| included(include, rel_path)),
+            _ => true,
+        }))
+}
+
+/// An entry to archive, paired with the path it is archived under.
+pub type Entry = Result<(PathBuf, walkdir::DirEntry), Error>;
+
+/// Entries to archive, each paired with the path it is archived under.
+pub type Entries = Box<dyn Iterator<Item = Entry> + Send>;
+
+/// Walks each source root in turn, like [`walk`], pairing every entry with the path it is
+/// archived under. With `--files-from`, the listed entries are archived instead.
+pub fn walk_sources(
+    sources: &[(PathBuf, PathBuf)],
+    options: &CompressOptions,
+) -> Result<Entries, Error> {
+    if let Some(list) = &options.files_from {
+        return files_from(list, options);
+    }
+    let mut walks = Vec::with_capacity(sources.len());
+    for (root, prefix) in sources {
+        let (root, prefix) = (root.clone(), prefix.clone());
+        walks.push(walk(&root, options)?.map(move |entry| {
+            let entry =
+                entry.with_context(|| format!("Failed to read entry in directory {:?}", root))?;
+            Ok((rel_path(&root, &prefix, &entry)?, entry))
+        }));
+    }
+    Ok(Box::new(walks.into_iter().flatten()))
+}
+
+/// Entries named in a `--files-from` list, one per line or NUL-terminated with `--null`. Each is
+/// archived under its path as listed, without the leading `/` or `./`, and directories are not
+/// recursed into, so the output of `find` can be given as is. The filters apply as if each path
+/// was walked from the top of the listed path.
+fn files_from(list: &Path, options: &CompressOptions) -> Result<Entries, Error> {
+    use std::io::BufRead;
+    let reader: Box<dyn std::io::Read + Send> = if utils::is_stdio(list) {
+        Box::new(std::io::stdin())
+    } else {
+        Box::new(
+            std::fs::File::open(list)
+                .with_context(|| format!("Failed to open file list {:?}", list))?,
+        )
+    };
+    let (null, follow_links) = (options.null, options.follow_symlinks);
+    let list = list.to_path_buf();
+    let mut filter = Filter::new(Path::new(""), options)?;
+
+    let names = std::io::BufReader::new(reader).split(if null { b'\0' } else { b'\n' });
+    Ok(Box::new(names.filter_map(move |name| {
+        let mut name = match name {
+            Ok(name) => name,
+            Err(e) => {
+                return Some(Err(
+                    Error::from(e).context(format!("Failed to read file list {:?}", list))
+                ));
+            }
+        };
+        if !null && name.last() == Some(&b'\r') {
+            name.pop();
+        }
+        let path = path_from_bytes(name);
+        match listed_entry(&path, follow_links) {
+            Ok(Some((rel_path, entry))) if !filter.keep_listed(&rel_path, &entry) => None,
+            entry => entry.transpose(),
+        }
+    })))
+}
+
+/// Entry of a listed path, or nothing for an empty line or the top of the archive.
+fn listed_entry(
+    path: &Path,
+    follow_links: bool,
+) -> Result<Option<(PathBuf, walkdir::DirEntry)>, Error> {
+    let mut rel_path = PathBuf::new();
+    for component in path.components() {
+        match component {
+            std::path::Component::Normal(name) => rel_path.push(name),
+            std::path::Component::ParentDir => {
+                return Err(Error::msg(format!(
+                    "Cannot archive {:?}, listed paths must not contain `..`",
+                    path
+                )));
+            }
+            _ => {}
+        }
+    }
+    if rel_path.as_os_str().is_empty() {
+        return Ok(None);
+    }
+
+    let entry = walkdir::WalkDir::new(path)
+        .follow_links(follow_links)
+        .max_depth(0)
+        .into_iter()
+        .next()
+        .ok_or_else(|| Error::msg(format!("Failed to read {:?}", path)))?
+        .with_context(|| format!("Failed to read {:?}", path))?;
+    Ok(Some((rel_path, entry)))
+}
+
+#[cfg(unix)]
+pub fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt;
+    PathBuf::from(std::ffi::OsString::from_vec(bytes))
+}
+
+#[cfg(not(unix))]
+pub fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+#[cfg(unix)]
+pub fn path_bytes(path: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+pub fn path_bytes(path: &Path) -> Vec<u8> {
+    path.to_string_lossy().as_bytes().to_vec()
+}
+
+/// Source roots, for messages.
+pub fn roots(sources: &[(PathBuf, PathBuf)]) -> Vec<PathBuf> {
+    sources.iter().map(|(root, _)| root.clone()).collect()
+}
+
+/// Path an entry is archived under: relative to `root` below `prefix`, or the file name when
+/// `root` is a single file without a prefix. Empty for a root directory without a prefix,
+/// which is not archived.
+pub fn rel_path(root: &Path, prefix: &Path, entry: &walkdir::DirEntry) -> Result<PathBuf, Error> {
+    if entry.depth() == 0 && !entry.file_type().is_dir() && prefix.as_os_str().is_empty() {
+        return Ok(PathBuf::from(entry.file_name()));
+    }
+    let path = entry.path();
+    let rel_path = path
+        .strip_prefix(root)
+        .with_context(|| format!("Failed to strip {:?} by {:?}", path, root))?;
+    // Joining an empty path would add a trailing separator
+    if rel_path.as_os_str().is_empty() {
+        return Ok(prefix.to_path_buf());
+    }
+    Ok(prefix.join(rel_path))
+}
+
+/// Pairs each input with the path it is archived under: nothing for a single input, so a
+/// directory is archived by its contents, otherwise its base name. `--map SRC=DEST` puts the
+/// input `SRC` under `DEST` instead, `.` being the top of the archive.
+pub fn sources(inputs: &[PathBuf], maps: &[String]) -> Result<Vec<(PathBuf, PathBuf)>, Error> {
+    let mut mapped = Vec::with_capacity(maps.len());
+    for map in maps {
+        let (src, dest) = map
+            .split_once('=')
+            .ok_or_else(|| Error::msg(format!("Invalid --map {:?}, expected SRC=DEST", map)))?;
+        let dest = Path::new(dest.trim_matches('/'));
+        if dest.components().any(|component| {
+            !matches!(
+                component,
+                std::path::Component::Normal(_) | std::path::Component::CurDir
+            )
+        }) {
+            return Err(Error::msg(format!(
+                "Invalid --map {:?}, the destination must be relative and without `..`",
+                map
+            )));
+        }
+        let dest = normalized(dest);
+        mapped.push((normalized(Path::new(src)), dest, map, false));
+    }
+
+    let mut sources: Vec<(PathBuf, PathBuf)> = Vec::with_capacity(inputs.len());
+    for input in inputs {
+        let key = normalized(input);
+        let prefix = match mapped.iter_mut().find(|(src, ..)| *src == key) {
+            Some((_, dest, _, used)) => {
+                *used = true;
+                dest.clone()
+            }
+            None if inputs.len() == 1 => PathBuf::new(),
+            None => base_name(input)?,
+        };
+        let clash = sources.iter().find(|(_, other)| *other == prefix);
+        if let (false, Some((other, _))) = (prefix.as_os_str().is_empty(), clash) {
+            return Err(Error::msg(format!(
+                "Both {:?} and {:?} would be archived as {:?}, use --map to move one",
+                other, input, prefix
+            )));
+        }
+        sources.push((input.clone(), prefix));
+    }
+
+    if let Some((_, _, map, _)) = mapped.iter().find(|(.., used)| !used) {
+        return Err(Error::msg(format!(
+            "Invalid --map {:?}, it does not match any input",
+            map
+        )));
+    }
+    Ok(sources)
+}
+
+/// Path without `.` components, so `./dir/` and `dir` compare equal.
+fn normalized(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| *component != std::path::Component::CurDir)
+        .collect()
+}
+
+/// Last component of an input, resolving paths like `.` that do not name it.
+fn base_name(input: &Path) -> Result<PathBuf, Error> {
+    if let Some(name) = input.file_name() {
+        return Ok(PathBuf::from(name));
+    }
+    let canonical = input
+        .canonicalize()
+        .with_context(|| format!("Failed to resolve path {:?}", input))?;
+    canonical
+        .file_name()
+        .map(PathBuf::from)
+        .ok_or_else(|| Error::msg(format!("Cannot archive {:?} without --map", input)))
+}
+
+/// Whether an entry, or a directory containing it, matches `--include`. Directories that do not
+/// match are still descended into, only left out of the archive.
+fn included(include: &globset::GlobSet, rel_path: &Path) -> bool {
+    if rel_path.as_os_str().is_empty() {
+        return true;
+    }
+    rel_path
+        .ancestors()
+        .take_while(|ancestor| !ancestor.as_os_str().is_empty())
+        .any(|ancestor| include.is_match(ancestor))
+}
+
+/// Builds a glob set in the style of `tar --exclude`: patterns with a `/` match the path
+/// relative to the source directory, other patterns match the name at any depth.
+fn glob_set(patterns: &[String]) -> Result<globset::GlobSet, Error> {
+    let mut builder = globset::GlobSetBuilder::new();
+    for pattern in patterns {
+        let trimmed = pattern.trim_start_matches("./").trim_end_matches('/');
+        let glob = if trimmed.contains('/') {
+            trimmed.to_string()
+        } else {
+            format!("**/{}", trimmed)
+        };
+        builder.add(
+            globset::GlobBuilder::new(&glob)
+                .literal_separator(true)
+                .build()
+                .with_context(|| format!("Invalid pattern {:?}", pattern))?,
+        );
+    }
+    Ok(builder.build()?)
+}
+
+/// Decides which entries are walked into, keeping the per directory state it needs.
+struct Filter {
+    root: PathBuf,
+    exclude: globset::GlobSet,
+    include: Option<globset::GlobSet>,
+    exclude_vcs: bool,
+    exclude_caches: bool,
+    respect_gitignore: bool,
+    /// Whether each directory seen so far carries a valid cache directory tag
+    caches: HashMap<PathBuf, bool>,
+    /// `.gitignore` and `.ignore` rules of each directory seen so far that has any
+    gitignores: HashMap<PathBuf, ignore::gitignore::Gitignore>,
+    /// Directories above `--files-from` paths already kept and loaded
+    listed_dirs: HashSet<PathBuf>,
+}
+
+impl Filter {
+    fn new(root: &Path, options: &CompressOptions) -> Result<Self, Error> {
+        let mut exclude = options.exclude.clone();
+        for file in &options.exclude_from {
+            let patterns = std::fs::read_to_string(file)
+                .with_context(|| format!("Failed to read exclude patterns from {:?}", file))?;
+            exclude.extend(
+                patterns
+                    .lines()
+                    .map(|line| line.trim_end_matches('\r'))
+                    .filter(|line| !line.is_empty())
+                    .map(str::to_string),
+            );
+        }
+
+        Ok(Self {
+            root: root.to_path_buf(),
+            exclude: glob_set(&exclude)?,
+            include: match options.include.is_empty() {
+                true => None,
+                false => Some(glob_set(&options.include)?),
+            },
+            exclude_vcs: options.exclude_vcs,
+            exclude_caches: options.exclude_caches,
+            respect_gitignore: options.respect_gitignore,
+            caches: HashMap::new(),
+            gitignores: HashMap::new(),
+            listed_dirs: HashSet::new(),
+        })
+    }
+
+    fn keep(&mut self, entry: &walkdir::DirEntry) -> bool {
+        let path = entry.path();
+        let Ok(rel_path) = path.strip_prefix(&self.root) else {
+            return true;
+        };
+        let is_dir = entry.file_type().is_dir();
+        if !rel_path.as_os_str().is_empty() && !self.keep_path(path, rel_path, is_dir) {
+            return false;
+        }
+        if is_dir {
+            self.enter(path);
+        }
+        true
+    }
+
+    /// Whether a `--files-from` entry is kept. The directories above it, up to the top of the
+    /// listed path, are filtered and loaded first, as the walk would have entered them, so
+    /// excluding a directory also leaves out what is listed below it.
+    fn keep_listed(&mut self, rel_path: &Path, entry: &walkdir::DirEntry) -> bool {
+        let path = entry.path();
+        let dirs = path.ancestors().zip(rel_path.ancestors()).skip(1);
+        // From the top of the listed path, the directory the path is relative to, downwards
+        for (dir, rel_dir) in dirs.collect::<Vec<_>>().into_iter().rev() {
+            if self.listed_dirs.contains(dir) {
+                continue;
+            }
+            if !rel_dir.as_os_str().is_empty() && !self.keep_path(dir, rel_dir, true) {
+                return false;
+            }
+            self.enter(dir);
+            self.listed_dirs.insert(dir.to_path_buf());
+        }
+        if let Some(include) = &self.include
+            && !included(include, rel_path)
+        {
+            return false;
+        }
+        self.keep_path(path, rel_path, entry.file_type().is_dir())
+    }
+
+    /// Whether the entry at `path`, archived as `rel_path` below the top of the walk, is kept.
+    fn keep_path(&self, path: &Path, rel_path: &Path, is_dir: bool) -> bool {
+        let name = path.file_name().unwrap_or_default();
+        if self.exclude_vcs && is_dir && VCS_DIRS.iter().any(|vcs| name == *vcs) {
+            return false;
+        }
+        if self.exclude.is_match(rel_path) {
+            return false;
+        }
+        // Like `tar --exclude-caches`, the tag itself is kept
+        if self.exclude_caches
+            && name != CACHEDIR_TAG
+            && path
+                .parent()
+                .is_some_and(|parent| self.caches.get(parent) == Some(&true))
+        {
+            return false;
+        }
+        if self.respect_gitignore && self.ignored(path, is_dir) {
+            return false;
+        }
+        true
+    }
+
+    /// Loads the state of a directory that is going to be walked into.
+    fn enter(&mut self, dir: &Path) {
+        if self.exclude_caches {
+            self.caches.insert(dir.to_path_buf(), is_cache_dir(dir));
+        }
+        if self.respect_gitignore {
+            let mut builder = ignore::gitignore::GitignoreBuilder::new(dir);
+            let mut any = false;
+            // `.ignore` comes last so that it takes precedence, as in ripgrep
+            for name in [".gitignore", ".ignore"] {
+                let file = dir.join(name);
+                if file.is_file() {
+                    // Invalid lines are skipped, like git does
+                    let _ = builder.add(file);
+                    any = true;
+                }
+            }
+            if let (true, Ok(gitignore)) = (any, builder.build()) {
+                self.gitignores.insert(dir.to_path_buf(), gitignore);
+            }
+        }
+    }
+
+    /// Whether the closest ignore file with a rule for `path` ignores it.
+    fn ignored(&self, path: &Path, is_dir: bool) -> bool {
+        for dir in path.ancestors().skip(1) {
+            if let Some(gitignore) = self.gitignores.get(dir) {
+                match gitignore.matched(path, is_dir) {
+                    ignore::Match::Ignore(_) => return true,
+                    ignore::Match::Whitelist(_) => return false,
+                    ignore::Match::None => {}
+                }
+            }
+            if dir == self.root {
+                break;
+            }
+        }
+        false
+    }
+}
+
+fn is_cache_dir(dir: &Path) -> bool {
+    use std::io::Read;
+    let mut signature = Vec::with_capacity(CACHEDIR_SIGNATURE.len());
+    std::fs::File::open(dir.join(CACHEDIR_TAG))
+        .and_then(|file| {
+            file.take(CACHEDIR_SIGNATURE.len() as u64)
+                .read_to_end(&mut signature)
+        })
+        .is_ok_and(|_| signature == CACHEDIR_SIGNATURE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn walked(root: &Path, options: &CompressOptions) -> Vec<String> {
+        let mut paths = walk(root, options)
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                let rel_path = entry.path().strip_prefix(root).unwrap();
+                rel_path.to_string_lossy().replace('\\', "/")
+            })
+            .filter(|path| !path.is_empty())
+            .collect::<Vec<_>>();
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn test_sources() {
+        let root = tempfile::tempdir().unwrap();
+        let inputs = ["./a", "b/", "c/file.txt"].map(PathBuf::from);
+        let maps = ["b=dest/prefix".to_string()];
+        assert_eq!(
+            sources(&inputs, &maps).unwrap(),
+            [
+                (PathBuf::from("./a"), PathBuf::from("a")),
+                (PathBuf::from("b/"), PathBuf::from("dest/prefix")),
+                (PathBuf::from("c/file.txt"), PathBuf::from("file.txt")),
+            ]
+        );
+
+        let single = [root.path().to_path_buf()];
+        assert_eq!(sources(&single, &[]).unwrap()[0].1, PathBuf::new());
+        let maps = [format!("{}=.", root.path().display())];
+        assert_eq!(sources(&single, &maps).unwrap()[0].1, PathBuf::new());
+
+        let clash = ["a/data", "b/data"].map(PathBuf::from);
+        assert!(sources(&clash, &[]).is_err());
+        assert!(sources(&clash, &["a/data=a".to_string()]).is_ok());
+        assert!(sources(&clash, &["missing=a".to_string()]).is_err());
+        assert!(sources(&clash, &["a/data=../a".to_string()]).is_err());
+        assert!(sources(&clash, &["a/data".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_files_from() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::create_dir(root.path().join("dir")).unwrap();
+        std::fs::write(root.path().join("dir/a.txt"), "a").unwrap();
+        std::fs::write(root.path().join("b.txt"), "b").unwrap();
+
+        let list = root.path().join("list");
+        let listed = |contents: String, null: bool| {
+            std::fs::write(&list, contents).unwrap();
+            let options = CompressOptions {
+                files_from: Some(list.clone()),
+                null,
+                ..Default::default()
+            };
+            walk_sources(&[], &options)
+                .unwrap()
+                .map(|entry| entry.map(|(rel_path, _)| rel_path))
+                .collect::<Result<Vec<_>, _>>()
+        };
+
+        // Listed paths lose their root, directories are archived alone
+        let relative = |path: PathBuf| {
+            path.components()
+                .filter(|component| matches!(component, std::path::Component::Normal(_)))
+                .collect::<PathBuf>()
+        };
+        let (dir, file) = (root.path().join("dir"), root.path().join("b.txt"));
+        assert_eq!(
+            listed(format!("{}\r\n\n", dir.display()), false).unwrap(),
+            [relative(dir.clone())]
+        );
+        assert_eq!(
+            listed(format!("{}\0{}\0", dir.display(), file.display()), true).unwrap(),
+            [relative(dir), relative(file)]
+        );
+
+        assert!(listed(format!("{}/missing", root.path().display()), false).is_err());
+        assert!(listed("dir/../b.txt".to_string(), false).is_err());
+
+        // The filters leave out listed paths, and what is listed below excluded directories
+        let files = ["dir/a.log", ".git/HEAD", "cache/CACHEDIR.TAG", "cache/blob"];
+        for file in files {
+            let path = root.path().join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, "data").unwrap();
+        }
+        std::fs::write(root.path().join("cache/CACHEDIR.TAG"), CACHEDIR_SIGNATURE).unwrap();
+        let listed_files = [
+            "dir",
+            "dir/a.txt",
+            "dir/a.log",
+            "b.txt",
+            ".git",
+            ".git/HEAD",
+            "cache",
+            "cache/CACHEDIR.TAG",
+            "cache/blob",
+        ];
+        let list_all = listed_files
+            .iter()
+            .map(|file| format!("{}\n", root.path().join(file).display()))
+            .collect::<String>();
+        std::fs::write(&list, list_all).unwrap();
+        let filtered = |options: CompressOptions| {
+            let options = CompressOptions {
+                files_from: Some(list.clone()),
+                ..options
+            };
+            walk_sources(&[], &options)
+                .unwrap()
+                .map(|entry| {
+                    let (rel_path, _) = entry.unwrap();
+                    let rel_path = rel_path.strip_prefix(relative(root.path().to_path_buf()));
+                    rel_path.unwrap().to_string_lossy().replace('\\', "/")
+                })
+                .collect::<Vec<_>>()
+        };
+        let options = CompressOptions {
+            exclude: vec!["*.log".to_string()],
+            exclude_vcs: true,
+            exclude_caches: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            filtered(options),
+            ["dir", "dir/a.txt", "b.txt", "cache", "cache/CACHEDIR.TAG"]
+        );
+        let options = CompressOptions {
+            include: vec!["dir".to_string(), "HEAD".to_string()],
+            exclude: vec![".git".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(filtered(options), ["dir", "dir/a.txt", "dir/a.log"]);
+    }
+
+    #[test]
+    fn test_walk_filters() {
+        let root = tempfile::tempdir().unwrap();
+        let files = [
+            "keep.txt",
+            "debug.log",
+            "logs/old.log",
+            "src/main.rs",
+            "src/gen/out.rs",
+            ".git/HEAD",
+            "cache/CACHEDIR.TAG",
+            "cache/blob",
+            "target/build",
+            "ignored.tmp",
+            "src/.ignore",
+        ];
+        for file in files {
+            let path = root.path().join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, "data").unwrap();
+        }
+        std::fs::write(root.path().join("cache/CACHEDIR.TAG"), CACHEDIR_SIGNATURE).unwrap();
+        std::fs::write(root.path().join(".gitignore"), "target/\n*.tmp\n").unwrap();
+        std::fs::write(root.path().join("src/.ignore"), "gen\n").unwrap();
+        let exclude_from = root.path().join(".gitignore");
+
+        let options = CompressOptions {
+            exclude: vec!["*.log".to_string()],
+            exclude_vcs: true,
+            exclude_caches: true,
+            respect_gitignore: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            walked(root.path(), &options),
+            [
+                ".gitignore",
+                "cache",
+                "cache/CACHEDIR.TAG",
+                "keep.txt",
+                "logs",
+                "src",
+                "src/.ignore",
+                "src/main.rs",
+            ]
+        );
+
+        let options = CompressOptions {
+            include: vec!["src".to_string(), "*.txt".to_string()],
+            exclude_from: vec![exclude_from],
+            ..Default::default()
+        };
+        assert_eq!(
+            walked(root.path(), &options),
+            [
+                "keep.txt",
+                "src",
+                "src/.ignore",
+                "src/gen",
+                "src/gen/out.rs",
+                "src/main.rs",
+            ]
+        );
+    }
+}
@@ -1,69 +1,135 @@
 use anyhow::{Context, Error, Result};
 use rayon::prelude::*;
-use std::io::{Read, Write};
+use std::io::Read;
 
-use crate::utils;
+use crate::args::{CompressOptions, ExtractOptions};
+use crate::{list, paths, select, utils, walk};
 
 /// Creates a zip file with dflate algorithm and writes it to the given output.
+///
+/// Only the DOS modification time is stored, no extended timestamp extra field, so a
+/// reproducible archive carries no time later than the clamped modification time. Each source
+/// root is archived below its prefix.
 pub fn zip<W: std::io::Write + std::io::Seek + ?Sized>(
-    src_dir: &std::path::Path,
+    sources: &[(std::path::PathBuf, std::path::PathBuf)],
     output: &mut W,
+    compress_options: &CompressOptions,
     log_level: u8,
 ) -> Result<(), Error> {
     let mut total_zip_writer = zip::ZipWriter::new(output);
+    zip_entries(sources, compress_options, log_level, |zip_archive| {
+        total_zip_writer.merge_archive(zip_archive)?;
+        Ok(())
+    })?;
+    total_zip_writer.finish()?;
+    Ok(())
+}
 
+/// Creates a zip file like [`zip`] on an output that cannot seek, such as a pipe. The sizes
+/// and checksum of each entry follow its data in a data descriptor.
+pub fn zip_stream<W: std::io::Write + ?Sized>(
+    sources: &[(std::path::PathBuf, std::path::PathBuf)],
+    output: &mut W,
+    compress_options: &CompressOptions,
+    log_level: u8,
+) -> Result<(), Error> {
+    let mut stream_writer = StreamWriter::new(output);
+    zip_entries(sources, compress_options, log_level, |zip_archive| {
+        stream_writer.append(zip_archive)
+    })?;
+    stream_writer.finish()
+}
+
+/// Compresses every entry of the sources into a single entry zip in memory, in parallel, and
+/// hands them to `merge` one at a time. The zip of a file larger than the memory budget goes to
+/// a temporary file once it outgrows the budget.
+fn zip_entries(
+    sources: &[(std::path::PathBuf, std::path::PathBuf)],
+    compress_options: &CompressOptions,
+    log_level: u8,
+    mut merge: impl FnMut(zip::ZipArchive<tempfile::SpooledTempFile>) -> Result<(), Error>,
+) -> Result<(), Error> {
     let options = zip::write::SimpleFileOptions::default()
         .compression_method(zip::CompressionMethod::Deflated);
 
     let (tx, rx) = std::sync::mpsc::sync_channel(100);
-    let src_dir_buf = src_dir.to_path_buf();
+    let roots = walk::roots(sources);
+    let reproducible = compress_options.reproducible;
+    let compress_options = compress_options.clone();
+
+    let walk = walk::walk_sources(sources, &compress_options)?;
+
+    // Memory is reserved in walk order, as for tarballs, and held until the entry is merged
+    let budget = compress_options.memory.clone();
+    let walk = walk.enumerate().map(move |(index, entry)| {
+        let bytes = match (&entry, budget.limit()) {
+            (Ok((_, entry)), Some(limit)) => entry.metadata().map_or(0, |metadata| match metadata
+                .is_file()
+            {
+                true => metadata.len().min(limit),
+                false => 0,
+            }),
+            _ => 0,
+        };
+        (index, entry, budget.reserve(bytes))
+    });
 
     let thread = std::thread::spawn(move || -> Result<(), Error> {
-        let result = walkdir::WalkDir::new(&src_dir_buf)
-            .into_iter()
-            .par_bridge()
-            .map(|entry| -> Result<(), Error> {
-                let entry = entry.with_context(|| {
-                    format!("Failed to read entry in directory {:?}", src_dir_buf)
-                })?;
-                if entry.file_type().is_dir() {
-                    return Ok(());
-                }
+        let zip_entry = |index, entry: walk::Entry, memory| -> Result<(), Error> {
+            let (relpath, entry) = entry?;
+            let path = entry.path();
+            let relpath_str = relpath.to_string_lossy().to_string();
+            if relpath_str.is_empty() {
+                tx.send((index, None))?;
+                return Ok(());
+            }
 
-                let path = entry.path();
-                let relpath_str = path
-                    .strip_prefix(&src_dir_buf)
-                    .with_context(|| format!("Failed to strip prefix from path {:?}", path))?
-                    .to_string_lossy()
-                    .to_string();
-
-                let raw_size = path
-                    .metadata()
-                    .with_context(|| format!("Failed to get metadata for path {:?}", path))?
-                    .len();
-
-                let mut buff = std::io::Cursor::new(Vec::new());
-                {
-                    let mut zip_writer = zip::ZipWriter::new(&mut buff);
-                    zip_writer.start_file(&relpath_str, options.clone().large_file(raw_size > 0xFFFFFFFF))?;
-                    let data = std::fs::read(path)?;
-                    zip_writer.write_all(&data)?;
-                    zip_writer.finish()?;
+            let metadata = path
+                .metadata()
+                .with_context(|| format!("Failed to get metadata for path {:?}", path))?;
+            let raw_size = if metadata.is_dir() { 0 } else { metadata.len() };
+            let options = entry_options(options, &compress_options, &metadata);
+
+            let spool_size = compress_options.memory.limit().map_or(usize::MAX, |limit| {
+                usize::try_from(limit).unwrap_or(usize::MAX)
+            });
+            let mut buff = tempfile::SpooledTempFile::new(spool_size);
+            {
+                let mut zip_writer = zip::ZipWriter::new(&mut buff);
+                if metadata.is_dir() {
+                    zip_writer.add_directory(relpath_str.as_str(), options)?;
+                } else {
+                    zip_writer
+                        .start_file(&relpath_str, options.large_file(raw_size > 0xFFFFFFFF))?;
+                    std::io::copy(&mut std::fs::File::open(path)?, &mut zip_writer)?;
                 }
+                zip_writer.finish()?;
+            }
 
-                let zip_archive = zip::ZipArchive::new(buff)?;
+            let zip_archive = zip::ZipArchive::new(buff)?;
 
-                tx.send((relpath_str, zip_archive, raw_size))?;
+            tx.send((index, Some((relpath_str, zip_archive, raw_size, memory))))?;
 
-                Ok(())
+            Ok(())
+        };
+        let result = walk
+            .par_bridge()
+            .map(|(index, entry, memory)| {
+                let result = zip_entry(index, entry, memory);
+                if result.is_err() {
+                    // The merge still needs the position, or holds back the entries after it
+                    // along with their memory
+                    tx.send((index, None)).ok();
+                }
+                result
             })
             .filter(|result| !result.is_ok())
             .collect::<Vec<_>>();
 
         if !result.is_empty() {
             Result::Err(Error::msg(format!(
-                "Failed to process all files in directory {:?}: {:?}",
-                src_dir_buf, result
+                "Failed to process all files in {:?}: {:?}",
+                roots, result
             )))
         } else {
             Ok(())
@@ -72,19 +138,33 @@ pub fn zip<W: std::io::Write + std::io::Seek + ?Sized>(
 
     let progress = utils::Progress::new(log_level, "+".to_string());
 
-    while let Ok((relpath_str, zip_archive, raw_size)) = rx.recv() {
-        total_zip_writer.merge_archive(zip_archive).with_context(|| {
-            format!(
-                "Failed to append data for file {:?} to zip archive",
-                &relpath_str
-            )
-        })?;
-        progress
-            .tx
-            .send(utils::ProgressData::Data((relpath_str, raw_size)))?;
+    // Merge the entries, in walk order when reproducible
+
+    let mut merge_entry =
+        |(relpath_str, zip_archive, raw_size, _memory): (String, _, u64, utils::Reservation)| {
+            merge(zip_archive).with_context(|| {
+                format!(
+                    "Failed to append data for file {:?} to zip archive",
+                    &relpath_str
+                )
+            })?;
+            progress
+                .tx
+                .send(utils::ProgressData::Data((relpath_str, raw_size)))?;
+            Ok::<(), Error>(())
+        };
+    let mut reorder = utils::Reorder::new(reproducible);
+    while let Ok((index, entry)) = rx.recv() {
+        reorder
+            .push(index, entry)
+            .into_iter()
+            .try_for_each(&mut merge_entry)?;
     }
+    reorder
+        .finish()
+        .into_iter()
+        .try_for_each(&mut merge_entry)?;
     progress.join()?;
-    total_zip_writer.finish()?;
 
     match thread.join() {
         Ok(result) => result,
@@ -92,9 +172,335 @@ pub fn zip<W: std::io::Write + std::io::Seek + ?Sized>(
     }
 }
 
+/// Writes zip entries to an output that cannot seek, with general purpose bit 3 set so that the
+/// sizes and checksum of each entry come in a data descriptor after its data.
+struct StreamWriter<'a, W: std::io::Write + ?Sized> {
+    output: &'a mut W,
+    /// Bytes written so far, the offset of the next local header
+    offset: u64,
+    central_directory: Vec<u8>,
+    entries: u64,
+}
+
+impl<'a, W: std::io::Write + ?Sized> StreamWriter<'a, W> {
+    fn new(output: &'a mut W) -> Self {
+        Self {
+            output,
+            offset: 0,
+            central_directory: Vec::new(),
+            entries: 0,
+        }
+    }
+
+    /// Copies the already compressed entries of `zip_archive` to the output.
+    fn append(
+        &mut self,
+        mut zip_archive: zip::ZipArchive<tempfile::SpooledTempFile>,
+    ) -> Result<(), Error> {
+        for i in 0..zip_archive.len() {
+            let mut file = zip_archive.by_index_raw(i)?;
+            let name = file.name().as_bytes().to_vec();
+            let method: u16 = match file.compression() {
+                zip::CompressionMethod::Stored => 0,
+                zip::CompressionMethod::Deflated => 8,
+                method => {
+                    return Err(Error::msg(format!(
+                        "Unsupported compression method {} for streaming",
+                        method
+                    )));
+                }
+            };
+            // 1980-01-01 00:00:00 when the entry has no time
+            let (time, date) = file.last_modified().map_or((0, 0x21), |date_time| {
+                (date_time.timepart(), date_time.datepart())
+            });
+            let crc32 = file.crc32();
+            let compressed_size = file.compressed_size();
+            let size = file.size();
+            let external_attributes =
+                file.unix_mode().unwrap_or(0) << 16 | if file.is_dir() { 0x10 } else { 0 };
+
+            let zip64 = compressed_size >= 0xFFFFFFFF || size >= 0xFFFFFFFF;
+            let version: u16 = if zip64 { 45 } else { 20 };
+            // Data descriptor and UTF-8 names
+            let flags: u16 = 1 << 3 | 1 << 11;
+            let offset = self.offset;
+
+            // Local header, sizes and checksum are left to the data descriptor
+
+            let mut header = Vec::with_capacity(30 + name.len() + 20);
+            header.extend_from_slice(&0x04034b50u32.to_le_bytes());
+            header.extend_from_slice(&version.to_le_bytes());
+            header.extend_from_slice(&flags.to_le_bytes());
+            header.extend_from_slice(&method.to_le_bytes());
+            header.extend_from_slice(&time.to_le_bytes());
+            header.extend_from_slice(&date.to_le_bytes());
+            header.extend_from_slice(&0u32.to_le_bytes());
+            let placeholder: u32 = if zip64 { 0xFFFFFFFF } else { 0 };
+            header.extend_from_slice(&placeholder.to_le_bytes());
+            header.extend_from_slice(&placeholder.to_le_bytes());
+            header.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            header.extend_from_slice(&(if zip64 { 20u16 } else { 0 }).to_le_bytes());
+            header.extend_from_slice(&name);
+            if zip64 {
+                // Announces 8 byte sizes in the data descriptor
+                header.extend_from_slice(&1u16.to_le_bytes());
+                header.extend_from_slice(&16u16.to_le_bytes());
+                header.extend_from_slice(&[0; 16]);
+            }
+            self.output.write_all(&header)?;
+
+            let copied = std::io::copy(&mut file, &mut self.output)?;
+            if copied != compressed_size {
+                return Err(Error::msg(format!(
+                    "Copied {} bytes of {:?} instead of {}",
+                    copied,
+                    file.name(),
+                    compressed_size
+                )));
+            }
+
+            let mut descriptor = Vec::with_capacity(24);
+            descriptor.extend_from_slice(&0x08074b50u32.to_le_bytes());
+            descriptor.extend_from_slice(&crc32.to_le_bytes());
+            if zip64 {
+                descriptor.extend_from_slice(&compressed_size.to_le_bytes());
+                descriptor.extend_from_slice(&size.to_le_bytes());
+            } else {
+                descriptor.extend_from_slice(&(compressed_size as u32).to_le_bytes());
+                descriptor.extend_from_slice(&(size as u32).to_le_bytes());
+            }
+            self.output.write_all(&descriptor)?;
+
+            self.offset += header.len() as u64 + compressed_size + descriptor.len() as u64;
+
+            // Central directory header, with the values that do not fit in a zip64 extra field
+
+            let mut extra = Vec::new();
+            if zip64 {
+                extra.extend_from_slice(&size.to_le_bytes());
+                extra.extend_from_slice(&compressed_size.to_le_bytes());
+            }
+            if offset >= 0xFFFFFFFF {
+                extra.extend_from_slice(&offset.to_le_bytes());
+            }
+            if !extra.is_empty() {
+                let mut field = Vec::with_capacity(4 + extra.len());
+                field.extend_from_slice(&1u16.to_le_bytes());
+                field.extend_from_slice(&(extra.len() as u16).to_le_bytes());
+                field.extend_from_slice(&extra);
+                extra = field;
+            }
+
+            let central = &mut self.central_directory;
+            central.extend_from_slice(&0x02014b50u32.to_le_bytes());
+            // Made by Unix
+            central.extend_from_slice(&(3 << 8 | version).to_le_bytes());
+            central.extend_from_slice(&version.to_le_bytes());
+            central.extend_from_slice(&flags.to_le_bytes());
+            central.extend_from_slice(&method.to_le_bytes());
+            central.extend_from_slice(&time.to_le_bytes());
+            central.extend_from_slice(&date.to_le_bytes());
+            central.extend_from_slice(&crc32.to_le_bytes());
+            central.extend_from_slice(&(compressed_size.min(0xFFFFFFFF) as u32).to_le_bytes());
+            central.extend_from_slice(&(size.min(0xFFFFFFFF) as u32).to_le_bytes());
+            central.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            central.extend_from_slice(&(extra.len() as u16).to_le_bytes());
+            // Comment length, disk number and internal attributes
+            central.extend_from_slice(&[0; 6]);
+            central.extend_from_slice(&external_attributes.to_le_bytes());
+            central.extend_from_slice(&(offset.min(0xFFFFFFFF) as u32).to_le_bytes());
+            central.extend_from_slice(&name);
+            central.extend_from_slice(&extra);
+
+            self.entries += 1;
+        }
+        Ok(())
+    }
+
+    /// Writes the central directory and the end of central directory records.
+    fn finish(self) -> Result<(), Error> {
+        let offset = self.offset;
+        let size = self.central_directory.len() as u64;
+        self.output.write_all(&self.central_directory)?;
+
+        let mut end = Vec::new();
+        if self.entries >= 0xFFFF || offset >= 0xFFFFFFFF || size >= 0xFFFFFFFF {
+            // Zip64 end of central directory record and locator
+            end.extend_from_slice(&0x06064b50u32.to_le_bytes());
+            end.extend_from_slice(&44u64.to_le_bytes());
+            end.extend_from_slice(&(3u16 << 8 | 45).to_le_bytes());
+            end.extend_from_slice(&45u16.to_le_bytes());
+            end.extend_from_slice(&[0; 8]);
+            end.extend_from_slice(&self.entries.to_le_bytes());
+            end.extend_from_slice(&self.entries.to_le_bytes());
+            end.extend_from_slice(&size.to_le_bytes());
+            end.extend_from_slice(&offset.to_le_bytes());
+
+            end.extend_from_slice(&0x07064b50u32.to_le_bytes());
+            end.extend_from_slice(&0u32.to_le_bytes());
+            end.extend_from_slice(&(offset + size).to_le_bytes());
+            end.extend_from_slice(&1u32.to_le_bytes());
+        }
+        let entries = self.entries.min(0xFFFF) as u16;
+        end.extend_from_slice(&0x06054b50u32.to_le_bytes());
+        end.extend_from_slice(&[0; 4]);
+        end.extend_from_slice(&entries.to_le_bytes());
+        end.extend_from_slice(&entries.to_le_bytes());
+        end.extend_from_slice(&(size.min(0xFFFFFFFF) as u32).to_le_bytes());
+        end.extend_from_slice(&(offset.min(0xFFFFFFFF) as u32).to_le_bytes());
+        end.extend_from_slice(&0u16.to_le_bytes());
+        self.output.write_all(&end)?;
+        self.output.flush()?;
+        Ok(())
+    }
+}
+
+/// Copies the modification time and, on Unix, the permissions of a file into its zip options.
+fn entry_options(
+    options: zip::write::SimpleFileOptions,
+    compress_options: &CompressOptions,
+    metadata: &std::fs::Metadata,
+) -> zip::write::SimpleFileOptions {
+    let mtime = compress_options
+        .mtime(metadata)
+        .map_or(0, |duration| duration.as_secs() as i64);
+    let (year, month, day, hour, minute, second) = utils::civil_from_unix(mtime);
+    let options = match zip::DateTime::from_date_and_time(
+        year.clamp(1980, 2107) as u16,
+        month as u8,
+        day as u8,
+        hour as u8,
+        minute as u8,
+        second as u8,
+    ) {
+        Ok(date_time) => options.last_modified_time(date_time),
+        Err(_) => options.last_modified_time(zip::DateTime::default()),
+    };
+
+    #[cfg(unix)]
+    let options = {
+        use std::os::unix::fs::PermissionsExt;
+        options.unix_permissions(metadata.permissions().mode())
+    };
+
+    options
+}
+
+/// Converts a zip timestamp back to seconds since the Unix epoch.
+fn unix_mtime(date_time: zip::DateTime) -> u64 {
+    utils::unix_from_civil(
+        date_time.year() as i64,
+        date_time.month() as u32,
+        date_time.day() as u32,
+        date_time.hour() as u32,
+        date_time.minute() as u32,
+        date_time.second() as u32,
+    )
+    .max(0) as u64
+}
+
+/// Lists the entries of a zip file from its central directory, without decompressing them.
+pub fn list_zip<R: std::io::Read + std::io::Seek + ?Sized>(
+    input: &mut R,
+    mut visit: impl FnMut(list::Entry) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let mut archive = zip::ZipArchive::new(input)?;
+    for i in 0..archive.len() {
+        let file = archive.by_index_raw(i)?;
+        let unix_mode = file.unix_mode();
+        let kind = match unix_mode.map(|mode| mode & 0o170000) {
+            _ if file.is_dir() => list::Kind::Directory,
+            Some(0o120000) => list::Kind::Symlink,
+            Some(0o100000) | Some(0) | None => list::Kind::File,
+            Some(_) => list::Kind::Other,
+        };
+        let mode = unix_mode.unwrap_or(match kind {
+            list::Kind::Directory => 0o755,
+            _ => 0o644,
+        }) & 0o7777;
+
+        let mut entry = list::Entry {
+            path: file.name().to_string(),
+            kind,
+            mode,
+            size: file.size(),
+            mtime: file.last_modified().map_or(0, unix_mtime) as i64,
+            owner: None,
+            link: None,
+            compression: Some((file.compressed_size(), file.compression().to_string())),
+        };
+        drop(file);
+
+        // Symlinks store their target as content
+        if entry.kind == list::Kind::Symlink {
+            let mut target = String::new();
+            archive.by_index(i)?.read_to_string(&mut target)?;
+            entry.link = Some(target);
+        }
+
+        visit(entry)?;
+    }
+    Ok(())
+}
+
+/// Decompresses every entry of a zip file without writing anything, verifying their CRC32.
+/// Returns the entries that failed.
+pub fn verify_zip<R: std::io::Read + std::io::Seek + ?Sized>(
+    input: &mut R,
+    log_level: u8,
+) -> Result<Vec<Error>, Error> {
+    let progress = utils::Progress::new(log_level, "+".to_string());
+
+    let mut archive = zip::ZipArchive::new(input)?;
+    let mut failures = Vec::new();
+    for i in 0..archive.len() {
+        let mut name = format!("#{}", i);
+        // The CRC32 is checked when the end of the entry is read
+        let result = archive
+            .by_index(i)
+            .map_err(Error::from)
+            .and_then(|mut file| {
+                name = file.name().to_string();
+                Ok(std::io::copy(&mut file, &mut std::io::sink())?)
+            });
+        match result {
+            Ok(size) => progress.tx.send(utils::ProgressData::Data((name, size)))?,
+            Err(e) => failures.push(e.context(format!("Failed to verify {:?}", name))),
+        }
+    }
+
+    progress.join()?;
+
+    Ok(failures)
+}
+
+/// Writes an extracted file to `dest_path` from `data`, replacing a file in its way. Returns the
+/// bytes written.
+fn write_entry(dest_path: &std::path::Path, data: &mut dyn Read) -> Result<u64, Error> {
+    // Handle existing file
+    if dest_path.exists() {
+        std::fs::remove_file(dest_path)?
+    }
+
+    // Handle parent directory
+    if let Some(parent) = dest_path.parent() {
+        if !parent.exists() {
+            std::fs::create_dir_all(parent)?;
+        } else if parent.is_file() {
+            std::fs::remove_file(parent)?;
+            std::fs::create_dir_all(parent)?;
+        }
+    }
+
+    let mut file = std::fs::File::create(dest_path)?;
+    Ok(std::io::copy(data, &mut file)?)
+}
+
 pub fn unzip<R: std::io::Read + std::io::Seek + ?Sized>(
     input: &mut R,
     dest_dir: &std::path::Path,
+    options: &ExtractOptions,
     log_level: u8,
 ) -> Result<(), Error> {
     let (tx, rx) = crossbeam::channel::bounded(100);
@@ -104,24 +510,10 @@ pub fn unzip<R: std::io::Read + std::io::Seek + ?Sized>(
         let result = rx
             .iter()
             .par_bridge()
-            .map(|(name, buf)| -> Result<(), Error> {
-                let dest_path = dest_dir_buf.join(&name);
-                // Handle existing file
-                if dest_path.exists() {
-                    std::fs::remove_file(&dest_path)?
-                }
-
-                // Handle parent directory
-                if let Some(parent) = dest_path.parent() {
-                    if !parent.exists() {
-                        std::fs::create_dir_all(parent)?;
-                    } else if parent.is_file() {
-                        std::fs::remove_file(parent)?;
-                        std::fs::create_dir_all(parent)?;
-                    }
-                }
-
-                std::fs::write(&dest_path, &buf)?;
+            .map(
+                |(name, buf, _memory): (std::path::PathBuf, Vec<u8>, utils::Reservation)|
+                 -> Result<(), Error> {
+                write_entry(&dest_dir_buf.join(&name), &mut buf.as_slice())?;
                 Ok(())
             })
             .filter(|result| !result.is_ok())
@@ -140,17 +532,56 @@ pub fn unzip<R: std::io::Read + std::io::Seek + ?Sized>(
     let progress = utils::Progress::new(log_level, "+".to_string());
 
     let dest_dir_buf = dest_dir.to_path_buf();
+    let mut selection = select::Selection::new(&options.patterns)?;
     let archive = &mut zip::ZipArchive::new(input)?;
     let num_files = archive.len();
+    let mut directories = Vec::new();
     let result = (0..num_files)
-        .into_iter()
         .map(|i| -> Result<(), Error> {
+            // Names come from the central directory, only selected entries are read. Unsafe
+            // names only fail when they are selected, as nothing else is written
+            let raw_name = std::path::Path::new(archive.name_for_index(i).unwrap_or_default());
+            let name = match paths::entry_path(raw_name, options.allow_unsafe_paths) {
+                Ok(name) => name,
+                Err(e) if selection.matches(raw_name) => return Err(e),
+                Err(_) => return Ok(()),
+            };
+            if !selection.matches(&name) {
+                return Ok(());
+            }
             let mut file = archive.by_index(i)?;
-            let name = file.name().to_string();
+            if file.is_dir() {
+                let dest_path = dest_dir_buf.join(&name);
+                std::fs::create_dir_all(&dest_path)
+                    .with_context(|| format!("Failed to create directory {:?}", dest_path))?;
+                if let Some(date_time) = file.last_modified() {
+                    directories.push((dest_path, unix_mtime(date_time)));
+                }
+                return Ok(());
+            }
+            // Written here in pieces, too large to hand to the writers whole
+            if options.memory.exceeds(file.size()) && !file.is_symlink() {
+                let dest_path = dest_dir_buf.join(&name);
+                let len = write_entry(&dest_path, &mut file)
+                    .with_context(|| format!("Failed to write file {:?}", dest_path))?;
+                progress
+                    .tx
+                    .send(utils::ProgressData::Data((file.name().to_string(), len)))?;
+                return Ok(());
+            }
+            let memory = options.memory.reserve(file.size());
             let mut buf = Vec::new();
             file.read_to_end(&mut buf)?;
+            if file.is_symlink() {
+                let target = String::from_utf8_lossy(&buf).to_string();
+                paths::check_link_target(
+                    &name,
+                    std::path::Path::new(&target),
+                    options.allow_unsafe_paths,
+                )?;
+            }
             let len = buf.len() as u64;
-            tx.send((name, buf))?;
+            tx.send((name, buf, memory))?;
             progress
                 .tx
                 .send(utils::ProgressData::Data((file.name().to_string(), len)))?;
@@ -170,24 +601,207 @@ pub fn unzip<R: std::io::Read + std::io::Seek + ?Sized>(
     progress.join()?;
 
     match thread.join() {
-        Ok(result) => result,
+        Ok(result) => result?,
         Err(_) => return Err(Error::msg("Thread panicked")),
     }
+
+    // Directory times last, writing the files would overwrite them
+
+    utils::set_dir_mtimes(&directories)?;
+
+    selection.check_unmatched()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::tests;
+    use std::io::Write;
 
     #[test]
     fn test_zip() {
         let mut tester = tests::tests::Tester::new();
 
-        zip(&tester.src_dir.path(), &mut tester.intermediate, 0).unwrap();
+        zip(
+            &tester.sources(),
+            &mut tester.intermediate,
+            &CompressOptions::default(),
+            0,
+        )
+        .unwrap();
+        tester.flush_intermediate();
+        unzip(
+            &mut tester.intermediate,
+            tester.dest_dir.path(),
+            &ExtractOptions::default(),
+            0,
+        )
+        .unwrap();
+
+        tester.assert();
+    }
+
+    #[test]
+    fn test_zip_max_memory() {
+        // The big test file is spooled to disk and extracted as it is read
+        const LIMIT: u64 = 1024 * 1024;
+        let mut tester = tests::tests::Tester::new();
+        let options = CompressOptions {
+            memory: utils::MemoryBudget::new(Some(LIMIT)),
+            ..Default::default()
+        };
+        zip(&tester.sources(), &mut tester.intermediate, &options, 0).unwrap();
         tester.flush_intermediate();
-        unzip(&mut tester.intermediate, &tester.dest_dir.path(), 0).unwrap();
+        assert!(options.memory.peak() <= LIMIT);
 
+        let options = ExtractOptions {
+            memory: utils::MemoryBudget::new(Some(LIMIT)),
+            ..Default::default()
+        };
+        unzip(
+            &mut tester.intermediate,
+            tester.dest_dir.path(),
+            &options,
+            0,
+        )
+        .unwrap();
         tester.assert();
+        assert!(options.memory.peak() > 0);
+        assert!(options.memory.peak() <= LIMIT);
+    }
+
+    #[test]
+    fn test_unzip_selected() {
+        let mut tester = tests::tests::Tester::new();
+        zip(
+            &tester.sources(),
+            &mut tester.intermediate,
+            &CompressOptions::default(),
+            0,
+        )
+        .unwrap();
+
+        let options = ExtractOptions {
+            patterns: vec!["dir".to_string()],
+            ..Default::default()
+        };
+        unzip(
+            &mut tester.intermediate,
+            tester.dest_dir.path(),
+            &options,
+            0,
+        )
+        .unwrap();
+
+        let dest = tester.dest_dir.path();
+        assert!(dest.join("dir/test_small.txt").is_file());
+        assert!(dest.join("dir/empty").is_dir());
+        assert!(!dest.join("test_small.txt").exists());
+        assert!(!dest.join("test_big.txt").exists());
+
+        // An unsafe entry only fails when it is selected
+        let mut unsafe_zip = std::io::Cursor::new(Vec::new());
+        let mut writer = zip::ZipWriter::new(&mut unsafe_zip);
+        for name in ["docs/a.txt", "../evil"] {
+            writer
+                .start_file(name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(b"data").unwrap();
+        }
+        writer.finish().unwrap();
+        let dest_dir = tempfile::tempdir().unwrap();
+        let dest = dest_dir.path().join("dest");
+        let options = ExtractOptions {
+            patterns: vec!["docs/*".to_string()],
+            ..Default::default()
+        };
+        unzip(&mut unsafe_zip, &dest, &options, 0).unwrap();
+        assert!(dest.join("docs/a.txt").is_file());
+        assert!(!dest_dir.path().join("evil").exists());
+        let options = ExtractOptions::default();
+        let error = unzip(&mut unsafe_zip, &dest, &options, 0).unwrap_err();
+        assert!(format!("{:#}", error).contains("../evil"));
+        assert!(!dest_dir.path().join("evil").exists());
+    }
+
+    #[test]
+    fn test_zip_stream() {
+        let mut tester = tests::tests::Tester::new();
+
+        // A Vec<u8> cannot seek, unlike the Cursor used by the other tests
+        let mut stream = Vec::new();
+        zip_stream(
+            &tester.sources(),
+            &mut stream,
+            &CompressOptions::default(),
+            0,
+        )
+        .unwrap();
+        tester.intermediate = std::io::Cursor::new(stream);
+
+        unzip(
+            &mut tester.intermediate,
+            tester.dest_dir.path(),
+            &ExtractOptions::default(),
+            0,
+        )
+        .unwrap();
+
+        tester.assert();
+    }
+
+    #[test]
+    fn test_verify_zip() {
+        let mut buff = std::io::Cursor::new(Vec::new());
+        {
+            let mut zip_writer = zip::ZipWriter::new(&mut buff);
+            let options = zip::write::SimpleFileOptions::default()
+                .compression_method(zip::CompressionMethod::Stored);
+            zip_writer.start_file("a.txt", options).unwrap();
+            zip_writer.write_all(b"corrupt me").unwrap();
+            zip_writer.start_file("b.txt", options).unwrap();
+            zip_writer.write_all(b"keep me").unwrap();
+            zip_writer.finish().unwrap();
+        }
+
+        let mut archive = buff.into_inner();
+        assert!(
+            verify_zip(&mut std::io::Cursor::new(archive.clone()), 0)
+                .unwrap()
+                .is_empty()
+        );
+
+        let data = archive
+            .windows(10)
+            .position(|w| w == b"corrupt me")
+            .unwrap();
+        archive[data] = b'C';
+        let failures = verify_zip(&mut std::io::Cursor::new(archive), 0).unwrap();
+        assert_eq!(failures.len(), 1);
+        assert!(format!("{:#}", failures[0]).contains("a.txt"));
+    }
+
+    #[test]
+    fn test_zip_reproducible() {
+        let tester = tests::tests::Tester::new();
+        let options = CompressOptions {
+            reproducible: true,
+            source_date_epoch: Some(1_500_000_000),
+            ..Default::default()
+        };
+        let archive = || {
+            let mut intermediate = std::io::Cursor::new(Vec::new());
+            zip(&tester.sources(), &mut intermediate, &options, 0).unwrap();
+            intermediate
+        };
+
+        let first = archive();
+        assert_eq!(first.get_ref(), archive().get_ref());
+
+        let mut zip_archive = zip::ZipArchive::new(first).unwrap();
+        for i in 0..zip_archive.len() {
+            let file = zip_archive.by_index(i).unwrap();
+            assert!(unix_mtime(file.last_modified().unwrap()) <= 1_500_000_000);
+        }
     }
 }
@@ -1,218 +1,1037 @@
-use std::io::{Read, Write};
+use std::io::{Read, Seek};
 
 use anyhow::{Context, Error, Result};
-use num_cpus;
 use rayon::prelude::*;
 
-use crate::utils;
+use crate::args::{CompressOptions, ExtractOptions};
+use crate::codec::{self, Codec};
+use crate::{frames, list, owner, paths, select, utils, walk};
+
+/// Largest zstd window log, for 64 bit and 32 bit targets.
+#[cfg(target_pointer_width = "64")]
+const WINDOW_LOG_MAX: u32 = 31;
+#[cfg(not(target_pointer_width = "64"))]
+const WINDOW_LOG_MAX: u32 = 30;
 
 struct TarFileData {
     rel_path: std::path::PathBuf,
-    file: std::fs::File,
-    cursor: Option<(std::io::Cursor<Vec<u8>>, tar::Header)>,
+    size: u64,
+    pax: Vec<(String, Vec<u8>)>,
+    /// (device, inode) of files with more than one link
+    inode: Option<(u64, u64)>,
+    content: TarContent,
+    /// Memory held by a buffered file, released once it is written
+    memory: utils::Reservation,
+}
+
+enum TarContent {
+    /// Small files and directories, read in parallel
+    Buffered(std::io::Cursor<Vec<u8>>, Box<tar::Header>),
+    /// Large files, streamed by the writer
+    File(std::fs::File, Box<tar::Header>),
+    /// Symlinks, with their target
+    Symlink(std::path::PathBuf, Box<tar::Header>),
+    /// Further links to a file already in the archive, with the path it was stored under
+    HardLink(std::path::PathBuf, Box<tar::Header>),
+}
+
+impl TarContent {
+    fn into_hard_link(self, target: std::path::PathBuf) -> Self {
+        let mut header = match self {
+            TarContent::Buffered(_, header)
+            | TarContent::File(_, header)
+            | TarContent::Symlink(_, header)
+            | TarContent::HardLink(_, header) => header,
+        };
+        header.set_entry_type(tar::EntryType::Link);
+        header.set_size(0);
+        TarContent::HardLink(target, header)
+    }
 }
 
 struct TarWriter;
 
 impl TarWriter {
-    fn start(
-        src_dir: &std::path::Path,
-        tar_builder: &mut tar::Builder<impl std::io::Write>,
-        small_file_size: u64,
+    fn start<W: std::io::Write>(
+        sources: &[(std::path::PathBuf, std::path::PathBuf)],
+        tar_builder: &mut tar::Builder<codec::Encoder<W>>,
+        options: &CompressOptions,
         log_level: u8,
     ) -> Result<std::thread::JoinHandle<Result<(), Error>>, Error> {
         let progress = utils::Progress::new(log_level, "+".to_string());
 
         let (tx, rx) = std::sync::mpsc::sync_channel(100);
-        let src_dir_buf = src_dir.to_path_buf();
+        let roots = walk::roots(sources);
+        let reproducible = options.reproducible;
+        let options = options.clone();
+
+        // Start the thread to process files in the directories
+
+        let walk = walk::walk_sources(sources, &options)?;
 
-        // Start the thread to process files in the directory
+        // Memory is reserved in walk order, before the readers take the entries, so the entry
+        // written next never waits for memory held by later ones
+        let walk_options = options.clone();
+        let walk = walk.enumerate().map(move |(index, entry)| {
+            let bytes = match &entry {
+                Ok((_, entry)) if walk_options.memory.limit().is_some() => {
+                    entry.metadata().map_or(0, |metadata| {
+                        TarWriter::buffered_size(&walk_options, &metadata)
+                    })
+                }
+                _ => 0,
+            };
+            (index, entry, walk_options.memory.reserve(bytes))
+        });
 
         let thread = std::thread::spawn(move || -> Result<(), Error> {
-            let result = walkdir::WalkDir::new(&src_dir_buf)
-                .into_iter()
-                .enumerate()
+            let result = walk
                 .par_bridge()
-                .map(|(_, entry)| -> Result<(), Error> {
-                    let entry = entry?;
-                    TarWriter::send_tar_data(small_file_size, &src_dir_buf, &tx, entry)
+                .map(|(index, entry, memory)| -> Result<(), Error> {
+                    let result = entry.and_then(|(rel_path, entry)| {
+                        TarWriter::send_tar_data(&options, rel_path, &tx, index, entry, memory)
+                    });
+                    if result.is_err() {
+                        // The writer still needs the position, or holds back the entries after
+                        // it along with their memory
+                        tx.send((index, None)).ok();
+                    }
+                    result
                 })
                 .filter(|result| !result.is_ok())
                 .collect::<Vec<_>>();
 
             if !result.is_empty() {
                 return Result::Err(Error::msg(format!(
-                    "Failed to process all files in directory {:?}: {:?}",
-                    src_dir_buf, result
+                    "Failed to process all files in {:?}: {:?}",
+                    roots, result
                 )));
             }
 
             Ok(())
         });
 
-        // Write the data to the tar archive
+        // Write the data to the tar archive, in walk order when reproducible
+
+        let mut hard_links = std::collections::HashMap::<_, std::path::PathBuf>::new();
+        let mut reorder = utils::Reorder::new(reproducible);
+        while let Ok((index, data)) = rx.recv() {
+            for data in reorder.push(index, data) {
+                TarWriter::append(tar_builder, &mut hard_links, &progress, data)?;
+            }
+        }
+        for data in reorder.finish() {
+            TarWriter::append(tar_builder, &mut hard_links, &progress, data)?;
+        }
 
-        while let Ok(mut data) = rx.recv() {
-            let err_msg = || {
-                format!(
-                    "Failed to append data for file {:?} to tar archive",
-                    data.rel_path
+        progress.join()?;
+
+        Ok(thread)
+    }
+
+    fn append<W: std::io::Write>(
+        tar_builder: &mut tar::Builder<codec::Encoder<W>>,
+        hard_links: &mut std::collections::HashMap<(u64, u64), std::path::PathBuf>,
+        progress: &utils::Progress,
+        data: TarFileData,
+    ) -> Result<(), Error> {
+        let err_msg = || {
+            format!(
+                "Failed to append data for file {:?} to tar archive",
+                data.rel_path
+            )
+        };
+
+        tar_builder.get_mut().index_member(&data.rel_path);
+
+        if !data.pax.is_empty() {
+            tar_builder
+                .append_pax_extensions(
+                    data.pax
+                        .iter()
+                        .map(|(key, value)| (key.as_str(), value.as_slice())),
                 )
-            };
+                .with_context(err_msg)?;
+        }
 
-            if let Some((mut cursor, mut header)) = data.cursor {
-                header.set_metadata(&data.file.metadata()?);
+        // The first path written for an inode keeps the data, later ones link to it
+
+        let mut content = data.content;
+        if let Some(inode) = data.inode {
+            match hard_links.get(&inode) {
+                Some(target) => content = content.into_hard_link(target.clone()),
+                None => {
+                    hard_links.insert(inode, data.rel_path.clone());
+                }
+            }
+        }
+
+        match content {
+            TarContent::Buffered(mut cursor, mut header) => {
                 tar_builder
                     .append_data(&mut header, &data.rel_path, &mut cursor)
                     .with_context(err_msg)?;
-            } else {
+            }
+            TarContent::File(file, mut header) => {
                 tar_builder
-                    .append_file(&data.rel_path, &mut data.file)
+                    .append_data(&mut header, &data.rel_path, file.take(data.size))
+                    .with_context(err_msg)?;
+            }
+            TarContent::Symlink(target, mut header) | TarContent::HardLink(target, mut header) => {
+                tar_builder
+                    .append_link(&mut header, &data.rel_path, &target)
                     .with_context(err_msg)?;
             }
-
-            progress.tx.send(utils::ProgressData::Data((
-                data.rel_path.to_string_lossy().to_string(),
-                data.file.metadata()?.len(),
-            )))?;
         }
 
-        progress.join()?;
+        progress.tx.send(utils::ProgressData::Data((
+            data.rel_path.to_string_lossy().to_string(),
+            data.size,
+        )))?;
 
-        Ok(thread)
+        // The buffer is written and dropped
+        drop(data.memory);
+
+        Ok(())
     }
 
     fn join(
-        src_dir: &std::path::Path,
+        sources: &[(std::path::PathBuf, std::path::PathBuf)],
         thread: std::thread::JoinHandle<Result<(), Error>>,
     ) -> Result<(), Error> {
         match thread.join() {
-            Err(e) => {
-                return Err(Error::msg(format!(
-                    "Failed to join thread for processing files in directory {:?}: {:?}",
-                    src_dir, e
-                )));
+            Err(e) => Err(Error::msg(format!(
+                "Failed to join thread for processing files in {:?}: {:?}",
+                walk::roots(sources),
+                e
+            ))),
+            Ok(Err(e)) => Err(Error::msg(format!(
+                "Failed to process all files in {:?}: {:?}",
+                walk::roots(sources),
+                e
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Builds the header of an entry, with owner names and PAX records for sub-second times.
+    ///
+    /// Reproducible headers have their owner reset to 0 without names, their modification time
+    /// clamped and no access time.
+    fn header(
+        options: &CompressOptions,
+        metadata: &std::fs::Metadata,
+    ) -> (tar::Header, Vec<(String, Vec<u8>)>) {
+        let mut header = tar::Header::new_gnu();
+        header.set_metadata(metadata);
+        if options.reproducible {
+            header.set_uid(0);
+            header.set_gid(0);
+        }
+
+        #[cfg(unix)]
+        if !options.reproducible {
+            use std::os::unix::fs::MetadataExt;
+            if let Some(name) = owner::user_name(metadata.uid()) {
+                let _ = header.set_username(&name);
             }
-            Ok(Err(e)) => {
-                return Err(Error::msg(format!(
-                    "Failed to process all files in directory {:?}: {:?}",
-                    src_dir, e
-                )));
+            if let Some(name) = owner::group_name(metadata.gid()) {
+                let _ = header.set_groupname(&name);
             }
-            _ => Ok(()),
         }
+
+        let mtime = options.mtime(metadata);
+        if let Some(mtime) = mtime {
+            header.set_mtime(mtime.as_secs());
+        }
+        let atime = metadata
+            .accessed()
+            .ok()
+            .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+            .filter(|_| !options.reproducible);
+
+        let mut pax = Vec::new();
+        for (key, time) in [("mtime", mtime), ("atime", atime)] {
+            let Some(duration) = time else {
+                continue;
+            };
+            if key == "atime" || duration.subsec_nanos() != 0 {
+                let value = format!("{}.{:09}", duration.as_secs(), duration.subsec_nanos());
+                pax.push((key.to_string(), value.into_bytes()));
+            }
+        }
+
+        (header, pax)
+    }
+
+    /// PAX records with the extended attributes of `path` selected by `--xattrs`/`--acls`.
+    #[cfg(unix)]
+    fn xattr_records(
+        options: &CompressOptions,
+        path: &std::path::Path,
+    ) -> Result<Vec<(String, Vec<u8>)>, Error> {
+        let mut records = Vec::new();
+        if !options.xattrs && !options.acls {
+            return Ok(records);
+        }
+        let mut names = xattr::list(path)
+            .with_context(|| format!("Failed to list extended attributes of {:?}", path))?
+            .collect::<Vec<_>>();
+        names.sort();
+        for name in names {
+            let name = name.to_string_lossy();
+            if !xattr_selected(&name, options.xattrs, options.acls) {
+                continue;
+            }
+            let value = xattr::get(path, name.as_ref()).with_context(|| {
+                format!("Failed to read extended attribute {} of {:?}", name, path)
+            })?;
+            if let Some(value) = value {
+                records.push((format!("{}{}", XATTR_PAX_PREFIX, name), value));
+            }
+        }
+        Ok(records)
     }
 
     fn send_tar_data(
-        small_file_size: u64,
-        src_dir: &std::path::Path,
-        tx: &std::sync::mpsc::SyncSender<TarFileData>,
+        options: &CompressOptions,
+        relpath: std::path::PathBuf,
+        tx: &std::sync::mpsc::SyncSender<(usize, Option<TarFileData>)>,
+        index: usize,
         entry: walkdir::DirEntry,
+        memory: utils::Reservation,
     ) -> Result<(), Error> {
         let path = entry.path();
-        if path.is_dir() {
-            return Ok(());
-        }
-
-        let relpath = path
-            .strip_prefix(&src_dir)
-            .with_context(|| format!("Failed to strip {:?} by {:?}", path, src_dir))?;
 
-        let file = std::fs::File::open(path)
-            .with_context(|| format!("Failed to open file {:?} for reading", path))?;
+        let metadata = entry
+            .metadata()
+            .with_context(|| format!("Failed to get metadata for {:?}", path))?;
 
-        if small_file_size > 0 && path.metadata()?.len() >= small_file_size {
-            // println!("Large file {}: {:?}", i, path);
-            tx.send(TarFileData {
-                file: file,
-                rel_path: relpath.to_path_buf(),
-                cursor: None,
-            })
-            .with_context(|| {
-                format!("Failed to send data for file {:?} to tar archive", relpath)
-            })?;
+        if relpath.as_os_str().is_empty() {
+            tx.send((index, None))?;
             return Ok(());
         }
 
-        let file_data = std::fs::read(path)
-            .with_context(|| format!("Failed to read file {:?} into memory", path))?;
+        let (header, mut pax) = TarWriter::header(options, &metadata);
+        let header = Box::new(header);
 
-        let cursor = std::io::Cursor::new(file_data);
-        let mut header = tar::Header::new_gnu();
-        header.set_metadata(&file.metadata()?);
+        #[cfg(unix)]
+        if !metadata.file_type().is_symlink() {
+            pax.extend(TarWriter::xattr_records(options, path)?);
+        }
 
-        tx.send(TarFileData {
-            file: file,
+        let content = if metadata.file_type().is_symlink() {
+            let target = std::fs::read_link(path)
+                .with_context(|| format!("Failed to read link {:?}", path))?;
+            TarContent::Symlink(target, header)
+        } else if !metadata.is_file() {
+            // Directories and special files only have a header
+            TarContent::Buffered(std::io::Cursor::new(Vec::new()), header)
+        } else if TarWriter::is_streamed(options, metadata.len()) {
+            let file = std::fs::File::open(path)
+                .with_context(|| format!("Failed to open file {:?} for reading", path))?;
+            TarContent::File(file, header)
+        } else {
+            let file_data = std::fs::read(path)
+                .with_context(|| format!("Failed to read file {:?} into memory", path))?;
+            TarContent::Buffered(std::io::Cursor::new(file_data), header)
+        };
+
+        let data = TarFileData {
             rel_path: relpath.to_path_buf(),
-            cursor: Some((cursor, header)),
-        })
-        .with_context(|| format!("Failed to send data for file {:?} to tar archive", relpath))?;
+            size: if metadata.is_file() {
+                metadata.len()
+            } else {
+                0
+            },
+            pax,
+            inode: TarWriter::inode(&metadata),
+            content,
+            memory,
+        };
+        tx.send((index, Some(data))).with_context(|| {
+            format!("Failed to send data for file {:?} to tar archive", relpath)
+        })?;
 
         Ok(())
     }
+
+    /// Whether a file of `len` bytes is streamed by the writer rather than read into memory:
+    /// from `small_file_size` on, or when it is larger than the memory budget.
+    fn is_streamed(options: &CompressOptions, len: u64) -> bool {
+        (options.small_file_size > 0 && len >= options.small_file_size)
+            || options.memory.exceeds(len)
+    }
+
+    /// Bytes of an entry read into memory, the size of the files that are not streamed.
+    fn buffered_size(options: &CompressOptions, metadata: &std::fs::Metadata) -> u64 {
+        match metadata.is_file() && !TarWriter::is_streamed(options, metadata.len()) {
+            true => metadata.len(),
+            false => 0,
+        }
+    }
+
+    /// Identifies files that have other hard links, so only one copy of their data is stored.
+    fn inode(metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            if metadata.is_file() && metadata.nlink() > 1 {
+                return Some((metadata.dev(), metadata.ino()));
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = metadata;
+        None
+    }
 }
 
-/// Creates a tarball compressed with Zstandard (zstd) algorithm and writes it to the given output.
-pub fn tar_zstd<W: std::io::Write + ?Sized>(
-    src_dir: &std::path::Path,
+/// Creates a tarball compressed with `codec` and writes it to the given output. Each source root
+/// is archived below its prefix.
+pub fn tar<W: std::io::Write + ?Sized>(
+    codec: Codec,
+    sources: &[(std::path::PathBuf, std::path::PathBuf)],
     output: &mut W,
-    compress_level: u8,
-    no_long_distance_matching: bool,
-    small_file_size: u64,
+    options: &CompressOptions,
     log_level: u8,
 ) -> Result<()> {
-    // ZSTD Encoder
+    // Encoder
+
+    let encoder = codec.encoder(output, options).with_context(|| {
+        format!(
+            "Failed to create {} encoder for {:?}",
+            codec.name(),
+            walk::roots(sources)
+        )
+    })?;
 
-    let err_msg = || format!("Failed to create zstd encoder for {:?}", src_dir);
+    // Tar Builder
+
+    let mut tar_builder = tar::Builder::new(encoder);
+
+    // Start
 
-    let level = compress_level.min(22).max(1);
+    let thread = TarWriter::start(sources, &mut tar_builder, options, log_level);
 
-    let mut zstd_encoder = zstd::stream::write::Encoder::new(output, level.into())?;
-    if !no_long_distance_matching {
+    // End
+
+    let encoder = tar_builder.into_inner()?;
+    let levels = encoder.adapted_levels();
+    encoder.finish()?;
+    if let (Some(levels), true) = (levels, log_level >= 1) {
+        let levels = levels
+            .iter()
+            .map(|(level, frames)| format!("{} ({} frames)", level, frames))
+            .collect::<Vec<_>>();
+        eprintln!("Compress levels : {}", levels.join(", "));
+    }
+    TarWriter::join(sources, thread?)
+}
+
+/// Creates a zstd encoder with the level, long distance matching, window, threads, checksums
+/// and dictionary of the options. An embedded dictionary is written first.
+pub fn encoder<W: std::io::Write>(
+    mut output: W,
+    options: &CompressOptions,
+) -> Result<zstd::stream::write::Encoder<'static, W>, Error> {
+    let dictionary = crate::dict::load(options.dictionary.as_deref())?;
+    if options.embed_dictionary {
+        output.write_all(&frames::dictionary_frame(&dictionary))?;
+    }
+    let mut zstd_encoder =
+        zstd::stream::write::Encoder::with_dictionary(output, options.compress_level, &dictionary)?;
+    if !options.no_long_distance_matching {
+        zstd_encoder.long_distance_matching(true)?;
+    }
+    if let Some(window_log) = options.window_log {
         zstd_encoder
-            .long_distance_matching(true)
-            .with_context(err_msg)?;
+            .window_log(window_log)
+            .with_context(|| format!("Failed to set zstd window log {}", window_log))?;
     }
 
-    zstd_encoder
-        .multithread(num_cpus::get() as u32)
-        .with_context(err_msg)?;
+    // A single thread compresses on the calling thread, without zstd's workers
+    if options.zstd_threads > 1 {
+        zstd_encoder
+            .multithread(options.zstd_threads)
+            .with_context(|| format!("Failed to start {} zstd threads", options.zstd_threads))?;
+    }
+    if let Some(job_size) = options.job_size {
+        zstd_encoder.set_parameter(zstd::zstd_safe::CParameter::JobSize(job_size))?;
+    }
+    if let Some(overlap_log) = options.overlap_log {
+        zstd_encoder.set_parameter(zstd::zstd_safe::CParameter::OverlapSizeLog(overlap_log))?;
+    }
 
-    // Tar Builder
+    // Lets `rpcc t` and `zstd -t` detect corruption
+    zstd_encoder.include_checksum(options.checksum)?;
 
-    let mut tar_builder = tar::Builder::new(zstd_encoder);
+    Ok(zstd_encoder)
+}
 
-    // Start
+/// Creates a zstd stream decoder with the dictionary, accepting every window the encoder can
+/// write, as `zstd --long=31` does.
+pub fn decoder<'a, R: std::io::BufRead>(
+    input: R,
+    dictionary: &[u8],
+) -> std::io::Result<zstd::stream::read::Decoder<'a, R>> {
+    let mut decoder = zstd::stream::read::Decoder::with_dictionary(input, dictionary)?;
+    decoder.window_log_max(WINDOW_LOG_MAX)?;
+    Ok(decoder)
+}
 
-    let thread = TarWriter::start(&src_dir, &mut tar_builder, small_file_size, log_level);
+/// Compresses a single file with Zstandard (zstd) algorithm, without a tar wrapper, like
+/// `zstd` does.
+pub fn compress_zst<W: std::io::Write + ?Sized>(
+    src_file: &std::path::Path,
+    output: &mut W,
+    options: &CompressOptions,
+    log_level: u8,
+) -> Result<(), Error> {
+    if !src_file.is_file() {
+        return Err(Error::msg(format!(
+            "Input path is not a file: {:?}, use -t tarzstd to compress a directory",
+            src_file
+        )));
+    }
+    let mut file = std::fs::File::open(src_file)
+        .with_context(|| format!("Failed to open file: {:?}", src_file))?;
 
-    // End
+    let progress = utils::Progress::new(log_level, "+".to_string());
 
-    let zstd_encoder = tar_builder.into_inner()?;
+    let mut zstd_encoder = encoder(output, options)
+        .with_context(|| format!("Failed to create zstd encoder for {:?}", src_file))?;
+    let size = std::io::copy(&mut file, &mut zstd_encoder)
+        .with_context(|| format!("Failed to compress {:?}", src_file))?;
     zstd_encoder.finish()?;
-    TarWriter::join(&src_dir, thread?)
+
+    let name = src_file.file_name().unwrap_or_default();
+    progress.tx.send(utils::ProgressData::Data((
+        name.to_string_lossy().to_string(),
+        size,
+    )))?;
+    progress.join()
 }
 
-/// Extracts a tarball compressed with Zstandard (zstd) algorithm from the given input.
-pub fn untar_zstd<R: std::io::Read + ?Sized>(
+/// Decompresses a single file compressed with Zstandard (zstd) algorithm to the given output.
+/// Returns the decompressed size.
+pub fn decompress_zst<R: std::io::Read + ?Sized, W: std::io::Write + ?Sized>(
+    input: &mut R,
+    output: &mut W,
+) -> Result<u64, Error> {
+    let mut zstd_decoder =
+        decoder(std::io::BufReader::new(input), &[]).context("Failed to create zstd decoder")?;
+    std::io::copy(&mut zstd_decoder, output).context("Failed to decompress zstd stream")
+}
+
+/// Decodes a single file compressed with Zstandard (zstd) algorithm without writing anything,
+/// verifying the frame checksums. Returns the failure, if any.
+pub fn verify_zst<R: std::io::Read + ?Sized>(input: &mut R) -> Result<Vec<Error>, Error> {
+    match decompress_zst(input, &mut std::io::sink()) {
+        Ok(_) => Ok(Vec::new()),
+        Err(e) => Ok(vec![e]),
+    }
+}
+
+/// Lists the entries of a tarball compressed with `codec`, and the zstd dictionary at
+/// `dictionary` if any. Only headers are parsed, the data of each entry is skipped.
+pub fn list_tar<R: std::io::Read + ?Sized>(
+    codec: Codec,
+    input: &mut R,
+    dictionary: Option<&std::path::Path>,
+    mut visit: impl FnMut(list::Entry) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let mut tar_archive =
+        tar::Archive::new(codec.decoder(input, dictionary, &Default::default())?);
+
+    for entry in tar_archive
+        .entries()
+        .context("Failed to read entries of tar archive")?
+    {
+        let entry = entry.context("Failed to read entry of tar archive")?;
+        visit(list_entry(&entry)?)?;
+    }
+
+    Ok(())
+}
+
+/// Lists the members of a seekable tarball through its member index, decoding only the frames
+/// holding their headers.
+pub fn list_members<R: Read + Seek>(
+    reader: &mut frames::SeekableReader<R>,
+    mut visit: impl FnMut(list::Entry) -> Result<(), Error>,
+) -> Result<(), Error> {
+    for (path, offset) in reader.members.clone() {
+        reader.seek(std::io::SeekFrom::Start(offset))?;
+        let mut tar_archive = tar::Archive::new(&mut *reader);
+        let entry = tar_archive
+            .entries()?
+            .next()
+            .ok_or_else(|| Error::msg(format!("No entry at the offset of {:?}", path)))?
+            .with_context(|| format!("Failed to read entry {:?} of tar archive", path))?;
+        visit(list_entry(&entry)?)?;
+    }
+
+    Ok(())
+}
+
+/// Listed entry of a tar member, from its header.
+fn list_entry<R: Read>(entry: &tar::Entry<R>) -> Result<list::Entry, Error> {
+    let header = entry.header();
+    let kind = match header.entry_type() {
+        tar::EntryType::Regular | tar::EntryType::Continuous => list::Kind::File,
+        tar::EntryType::Directory => list::Kind::Directory,
+        tar::EntryType::Symlink => list::Kind::Symlink,
+        tar::EntryType::Link => list::Kind::HardLink,
+        _ => list::Kind::Other,
+    };
+    // Fall back to ids when names are not stored
+    let name = |name: Option<&str>, id: std::io::Result<u64>| match name {
+        Some(name) if !name.is_empty() => name.to_string(),
+        _ => id.map_or_else(|_| "?".to_string(), |id| id.to_string()),
+    };
+
+    Ok(list::Entry {
+        path: entry.path()?.to_string_lossy().to_string(),
+        kind,
+        mode: header.mode().unwrap_or(0) & 0o7777,
+        size: entry.size(),
+        mtime: header.mtime().unwrap_or(0) as i64,
+        owner: Some((
+            name(header.username().ok().flatten(), header.uid()),
+            name(header.groupname().ok().flatten(), header.gid()),
+        )),
+        link: entry
+            .link_name()?
+            .map(|target| target.to_string_lossy().to_string()),
+        compression: None,
+    })
+}
+
+/// Metadata of an extracted entry, applied once its content has been written.
+struct EntryMetadata {
+    mode: u32,
+    mtime: filetime::FileTime,
+    atime: filetime::FileTime,
+    owner: Option<(u32, u32)>,
+    #[cfg_attr(not(unix), allow(dead_code))]
+    xattrs: Vec<(String, Vec<u8>)>,
+}
+
+impl EntryMetadata {
+    fn from_entry<R: std::io::Read>(
+        entry: &mut tar::Entry<R>,
+        options: &ExtractOptions,
+    ) -> Result<Self, Error> {
+        let header = entry.header();
+        let mut mtime = filetime::FileTime::from_unix_time(header.mtime()? as i64, 0);
+        let mut atime = mtime;
+        let mode = header.mode()?;
+
+        let owner = if options.same_owner {
+            let named = |name: Result<Option<&str>, _>, lookup: fn(&str) -> Option<u32>| match (
+                options.numeric_owner,
+                name,
+            ) {
+                (false, Ok(Some(name))) => lookup(name),
+                _ => None,
+            };
+            let uid = named(header.username(), owner::user_id).unwrap_or(header.uid()? as u32);
+            let gid = named(header.groupname(), owner::group_id).unwrap_or(header.gid()? as u32);
+            Some((uid, gid))
+        } else {
+            None
+        };
+
+        let mut xattrs = Vec::new();
+        if let Some(extensions) = entry.pax_extensions()? {
+            for extension in extensions {
+                let extension = extension?;
+                let Ok(key) = extension.key() else {
+                    continue;
+                };
+                let time = extension.value().ok().and_then(parse_pax_time);
+                match (key, time) {
+                    ("mtime", Some(time)) => mtime = time,
+                    ("atime", Some(time)) => atime = time,
+                    _ => {}
+                }
+                if let Some(name) = key
+                    .strip_prefix(XATTR_PAX_PREFIX)
+                    .filter(|name| xattr_selected(name, options.xattrs, options.acls))
+                {
+                    xattrs.push((name.to_string(), extension.value_bytes().to_vec()));
+                }
+            }
+        }
+
+        Ok(Self {
+            mode,
+            mtime,
+            atime,
+            owner,
+            xattrs,
+        })
+    }
+
+    /// Mode bits to apply, without setuid/setgid unless the owner is restored too.
+    #[cfg(unix)]
+    fn permissions(&self) -> std::fs::Permissions {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = if self.owner.is_some() {
+            self.mode & 0o7777
+        } else {
+            self.mode & 0o1777
+        };
+        std::fs::Permissions::from_mode(mode)
+    }
+
+    fn apply_to_file(&self, file: &std::fs::File) -> Result<(), Error> {
+        #[cfg(unix)]
+        {
+            if let Some((uid, gid)) = self.owner {
+                std::os::unix::fs::fchown(file, Some(uid), Some(gid))?;
+            }
+            file.set_permissions(self.permissions())?;
+            for (name, value) in &self.xattrs {
+                xattr::FileExt::set_xattr(file, name, value)
+                    .with_context(|| format!("Failed to set extended attribute {}", name))?;
+            }
+        }
+        filetime::set_file_handle_times(file, Some(self.atime), Some(self.mtime))?;
+        Ok(())
+    }
+
+    fn apply_to_symlink(&self, path: &std::path::Path) -> Result<(), Error> {
+        #[cfg(unix)]
+        if let Some((uid, gid)) = self.owner {
+            std::os::unix::fs::lchown(path, Some(uid), Some(gid))?;
+        }
+        filetime::set_symlink_file_times(path, self.atime, self.mtime)?;
+        Ok(())
+    }
+
+    fn apply_to_path(&self, path: &std::path::Path) -> Result<(), Error> {
+        #[cfg(unix)]
+        {
+            if let Some((uid, gid)) = self.owner {
+                std::os::unix::fs::chown(path, Some(uid), Some(gid))?;
+            }
+            std::fs::set_permissions(path, self.permissions())?;
+            for (name, value) in &self.xattrs {
+                xattr::set(path, name, value)
+                    .with_context(|| format!("Failed to set extended attribute {}", name))?;
+            }
+        }
+        filetime::set_file_times(path, self.atime, self.mtime)?;
+        Ok(())
+    }
+}
+
+enum Link {
+    /// Symlink with its target as stored in the archive
+    Symbolic(std::path::PathBuf),
+    /// Hard link with the path of its target relative to the destination directory
+    Hard(std::path::PathBuf),
+}
+
+impl Link {
+    fn create(
+        &self,
+        dest_dir: &std::path::Path,
+        dest_path: &std::path::Path,
+        metadata: &EntryMetadata,
+    ) -> Result<(), Error> {
+        if let Some(parent) = dest_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        if dest_path.symlink_metadata().is_ok() {
+            std::fs::remove_file(dest_path)?;
+        }
+        match self {
+            Link::Hard(target) => std::fs::hard_link(dest_dir.join(target), dest_path)?,
+            Link::Symbolic(target) => {
+                #[cfg(unix)]
+                std::os::unix::fs::symlink(target, dest_path)?;
+                #[cfg(windows)]
+                std::os::windows::fs::symlink_file(target, dest_path)?;
+                metadata.apply_to_symlink(dest_path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// PAX key prefix for extended attributes, as used by GNU tar and star.
+const XATTR_PAX_PREFIX: &str = "SCHILY.xattr.";
+
+/// Whether an extended attribute is selected by `--xattrs` (`user.*`, `security.*`) or
+/// `--acls` (`system.posix_acl_*`).
+fn xattr_selected(name: &str, xattrs: bool, acls: bool) -> bool {
+    (xattrs && (name.starts_with("user.") || name.starts_with("security.")))
+        || (acls && name.starts_with("system.posix_acl_"))
+}
+
+/// Parses a PAX time record such as `1700000000.123456789`.
+fn parse_pax_time(value: &str) -> Option<filetime::FileTime> {
+    let (secs, fraction) = value.split_once('.').unwrap_or((value, ""));
+    let secs = secs.parse::<i64>().ok()?;
+    let digits = fraction.get(..fraction.len().min(9))?;
+    let nanos = if digits.is_empty() {
+        0
+    } else {
+        digits.parse::<u32>().ok()? * 10u32.pow(9 - digits.len() as u32)
+    };
+    if value.starts_with('-') && nanos > 0 {
+        Some(filetime::FileTime::from_unix_time(
+            secs - 1,
+            1_000_000_000 - nanos,
+        ))
+    } else {
+        Some(filetime::FileTime::from_unix_time(secs, nanos))
+    }
+}
+
+/// An entry read from a tar archive, with its path already checked against the destination.
+enum TarItem<'a> {
+    Directory(std::path::PathBuf, EntryMetadata),
+    /// With the memory held by the data
+    File(
+        std::path::PathBuf,
+        Vec<u8>,
+        EntryMetadata,
+        utils::Reservation,
+    ),
+    /// A file that does not fit in the memory budget, with its data still to read from the
+    /// archive
+    Stream(std::path::PathBuf, &'a mut dyn Read, EntryMetadata),
+    Link(std::path::PathBuf, Link, EntryMetadata),
+}
+
+/// Reads every entry of a tarball compressed with `codec` into `sink`, then decodes the rest of
+/// the stream so that the checksum at its end is verified too.
+///
+/// Entries with unsafe paths are skipped and their errors returned. Entries that are not
+/// selected are skipped without reading their data. Without `buffer`, every file is handed over
+/// unread.
+fn read_tar<R: std::io::Read + ?Sized>(
+    codec: Codec,
+    input: &mut R,
+    options: &ExtractOptions,
+    selection: &mut select::Selection,
+    buffer: bool,
+    mut sink: impl FnMut(TarItem) -> Result<(), Error>,
+) -> Result<Vec<Error>, Error> {
+    let mut tar_archive =
+        tar::Archive::new(codec.decoder(input, options.dictionary.as_deref(), &options.memory)?);
+
+    let mut rejected = Vec::new();
+    for entry in tar_archive.entries()? {
+        let entry = entry.context("Failed to read entry of tar archive")?;
+        read_entry(entry, options, selection, buffer, &mut rejected, &mut sink)?;
+    }
+
+    std::io::copy(&mut tar_archive.into_inner(), &mut std::io::sink())
+        .with_context(|| format!("Failed to read the end of the {} stream", codec.name()))?;
+
+    Ok(rejected)
+}
+
+/// Reads one entry into `sink` when it is selected, if its data fits in what is left of the
+/// memory budget. Other files, or every file without `buffer`, are handed over unread, as the
+/// memory is held by the buffers waiting to be written and by the frames the entry is read
+/// from, which only this thread releases. Unsafe entries are skipped and their errors added to
+/// `rejected`.
+fn read_entry<R: std::io::Read>(
+    mut entry: tar::Entry<R>,
+    options: &ExtractOptions,
+    selection: &mut select::Selection,
+    buffer: bool,
+    rejected: &mut Vec<Error>,
+    sink: &mut impl FnMut(TarItem) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let path = entry.path()?.to_path_buf();
+    let rel_path = match paths::entry_path(&path, options.allow_unsafe_paths) {
+        Ok(rel_path) => rel_path,
+        // Only the entries that would be written are refused
+        Err(e) if selection.matches(&path) => {
+            rejected.push(e);
+            return Ok(());
+        }
+        Err(_) => return Ok(()),
+    };
+    if !selection.matches(&rel_path) {
+        return Ok(());
+    }
+    let link = match entry.link_name()? {
+        Some(link_name) if entry.header().entry_type().is_symlink() => {
+            paths::check_link_target(&rel_path, &link_name, options.allow_unsafe_paths)
+                .map(|_| Some(Link::Symbolic(link_name.to_path_buf())))
+        }
+        Some(link_name) => paths::entry_path(&link_name, options.allow_unsafe_paths)
+            .map(|target| Some(Link::Hard(target))),
+        None => Ok(None),
+    };
+    let link = match link {
+        Ok(link) => link,
+        Err(e) => {
+            rejected.push(e);
+            return Ok(());
+        }
+    };
+    let metadata = EntryMetadata::from_entry(&mut entry, options)
+        .with_context(|| format!("Failed to read metadata of {:?}", path))?;
+    if let Some(link) = link {
+        return sink(TarItem::Link(rel_path, link, metadata));
+    }
+    if entry.header().entry_type().is_dir() {
+        return sink(TarItem::Directory(rel_path, metadata));
+    }
+    let memory = match buffer && !options.memory.exceeds(entry.size()) {
+        true => options.memory.try_reserve(entry.size()),
+        false => None,
+    };
+    let Some(memory) = memory else {
+        return sink(TarItem::Stream(rel_path, &mut entry, metadata));
+    };
+    let mut buf = Vec::new();
+    entry
+        .read_to_end(&mut buf)
+        .with_context(|| format!("Failed to read data of {:?}", path))?;
+    sink(TarItem::File(rel_path, buf, metadata, memory))
+}
+
+/// Reads the selected members of a seekable tarball into `sink`, seeking to each one through
+/// the member index so that only the frames holding them are decoded.
+fn read_members<R: Read + Seek>(
+    reader: &mut frames::SeekableReader<R>,
+    options: &ExtractOptions,
+    selection: &mut select::Selection,
+    mut sink: impl FnMut(TarItem) -> Result<(), Error>,
+) -> Result<Vec<Error>, Error> {
+    let mut rejected = Vec::new();
+    for (path, offset) in reader.members.clone() {
+        if !selection.matches(&path) {
+            continue;
+        }
+        reader.seek(std::io::SeekFrom::Start(offset))?;
+        let mut tar_archive = tar::Archive::new(&mut *reader);
+        let entry = tar_archive
+            .entries()?
+            .next()
+            .ok_or_else(|| Error::msg(format!("No entry at the offset of {:?}", path)))?
+            .with_context(|| format!("Failed to read entry {:?} of tar archive", path))?;
+        read_entry(entry, options, selection, true, &mut rejected, &mut sink)?;
+    }
+    Ok(rejected)
+}
+
+/// Decodes a tarball compressed with `codec` without writing anything, verifying the checksums
+/// of the stream. The data of each file is read in pieces and dropped, never buffered whole.
+/// Returns the entries that failed.
+pub fn verify_tar<R: std::io::Read + ?Sized>(
+    codec: Codec,
+    input: &mut R,
+    options: &ExtractOptions,
+    log_level: u8,
+) -> Result<Vec<Error>, Error> {
+    let progress = utils::Progress::new(log_level, "+".to_string());
+
+    // Unsafe paths are not corruption, they are only refused when extracting
+    let options = ExtractOptions {
+        allow_unsafe_paths: true,
+        ..options.clone()
+    };
+    let result = read_tar(
+        codec,
+        input,
+        &options,
+        &mut select::Selection::all(),
+        false,
+        |item| {
+            if let TarItem::Stream(rel_path, data, _) = item {
+                let size = std::io::copy(data, &mut std::io::sink())
+                    .with_context(|| format!("Failed to read data of {:?}", rel_path))?;
+                progress.tx.send(utils::ProgressData::Data((
+                    rel_path.to_string_lossy().to_string(),
+                    size,
+                )))?;
+            }
+            Ok(())
+        },
+    );
+
+    progress.join()?;
+
+    // The stream cannot be resynchronized after corrupt data, so at most one entry fails
+    match result {
+        Ok(_) => Ok(Vec::new()),
+        Err(e) => Ok(vec![e]),
+    }
+}
+
+/// Extracts a tarball compressed with `codec` from the given input.
+pub fn untar<R: std::io::Read + ?Sized>(
+    codec: Codec,
     input: &mut R,
     dest_dir: &std::path::Path,
+    options: &ExtractOptions,
     log_level: u8,
 ) -> Result<(), Error> {
-    // Create destination directory if it doesn't exist
-
-    std::fs::create_dir_all(dest_dir)
-        .with_context(|| format!("Failed to create destination directory {:?}", dest_dir))?;
+    extract(dest_dir, options, log_level, |selection, sink| {
+        read_tar(codec, input, options, selection, true, sink)
+    })
+}
 
-    // ZSTD Decoder
+/// Extracts the selected members of a seekable tarball, without decoding the frames of the
+/// other members.
+pub fn untar_members<R: Read + Seek>(
+    reader: &mut frames::SeekableReader<R>,
+    dest_dir: &std::path::Path,
+    options: &ExtractOptions,
+    log_level: u8,
+) -> Result<(), Error> {
+    extract(dest_dir, options, log_level, |selection, sink| {
+        read_members(reader, options, selection, sink)
+    })
+}
 
-    let err_msg = || format!("Failed to create zstd decoder for {:?}", dest_dir);
+/// Writes a file extracted to `dest_path` from `data`, in pieces, and sets its metadata.
+/// Returns the bytes written.
+fn write_file(
+    dest_path: &std::path::Path,
+    data: &mut dyn Read,
+    metadata: &EntryMetadata,
+) -> Result<u64, Error> {
+    if let Some(parent) = dest_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut file = std::fs::File::create(dest_path)
+        .with_context(|| format!("Failed to create file {:?}", dest_path))?;
+    let size = std::io::copy(data, &mut file)
+        .with_context(|| format!("Failed to write file {:?}", dest_path))?;
+    metadata
+        .apply_to_file(&file)
+        .with_context(|| format!("Failed to set metadata of {:?}", dest_path))?;
+    Ok(size)
+}
 
-    let zstd_decoder = zstd::stream::read::Decoder::new(input).with_context(err_msg)?;
+/// Writes the entries read by `read` to `dest_dir`. The data of files is held in the memory
+/// budget until it is written, files larger than the budget are written as they are read.
+fn extract(
+    dest_dir: &std::path::Path,
+    options: &ExtractOptions,
+    log_level: u8,
+    read: impl FnOnce(
+        &mut select::Selection,
+        &mut dyn FnMut(TarItem) -> Result<(), Error>,
+    ) -> Result<Vec<Error>, Error>,
+) -> Result<(), Error> {
+    // Create destination directory if it doesn't exist
 
-    // Tar Archive
+    std::fs::create_dir_all(dest_dir)
+        .with_context(|| format!("Failed to create destination directory {:?}", dest_dir))?;
 
-    let mut tar_archive = tar::Archive::new(zstd_decoder);
+    let mut selection = select::Selection::new(&options.patterns)?;
 
     // Parallel writ
 
@@ -223,16 +1042,14 @@ pub fn untar_zstd<R: std::io::Read + ?Sized>(
             .iter()
             .par_bridge()
             .map(
-                |(path, buf, modified_time): (_, Vec<u8>, _)| -> Result<(), Error> {
-                    let dest_path = dest_dir_buf.join(&path);
-                    if let Some(parent) = dest_path.parent() {
-                        std::fs::create_dir_all(parent)?;
-                    }
-                    let mut file = std::fs::File::create(&dest_path)?;
-                    file.write_all(&buf)?;
-                    let modified_time = std::time::SystemTime::UNIX_EPOCH
-                        + std::time::Duration::from_secs(modified_time as u64);
-                    file.set_modified(modified_time)?;
+                |(path, buf, metadata, _memory): (
+                    _,
+                    Vec<u8>,
+                    EntryMetadata,
+                    utils::Reservation,
+                )|
+                 -> Result<(), Error> {
+                    write_file(&dest_dir_buf.join(&path), &mut buf.as_slice(), &metadata)?;
                     Ok(())
                 },
             )
@@ -252,61 +1069,974 @@ pub fn untar_zstd<R: std::io::Read + ?Sized>(
 
     let progress = utils::Progress::new(log_level, "+".to_string());
 
-    let entries = tar_archive.entries()?;
-    for entry in entries {
-        let mut entry = entry?;
-        let path = dest_dir.join(entry.path()?);
-        if let Some(parent) = path.parent() {
-            std::fs::create_dir_all(parent)?;
+    let mut directories = Vec::new();
+    let mut links = Vec::new();
+    let mut rejected = read(&mut selection, &mut |item| {
+        match item {
+            TarItem::Link(rel_path, link, metadata) => links.push((rel_path, link, metadata)),
+            TarItem::Directory(rel_path, metadata) => {
+                let dest_path = dest_dir.join(&rel_path);
+                std::fs::create_dir_all(&dest_path)
+                    .with_context(|| format!("Failed to create directory {:?}", dest_path))?;
+                directories.push((dest_path, metadata));
+            }
+            TarItem::File(rel_path, buf, metadata, memory) => {
+                let progress_data = (rel_path.to_string_lossy().to_string(), buf.len() as u64);
+                tx.send((rel_path, buf, metadata, memory))?;
+                progress.tx.send(utils::ProgressData::Data(progress_data))?;
+            }
+            TarItem::Stream(rel_path, data, metadata) => {
+                let size = write_file(&dest_dir.join(&rel_path), data, &metadata)?;
+                let progress_data = (rel_path.to_string_lossy().to_string(), size);
+                progress.tx.send(utils::ProgressData::Data(progress_data))?;
+            }
         }
-        let size = entry.size();
-        let mut buf = Vec::new();
-        let _ = entry.read_to_end(&mut buf);
-        let path = entry.path()?;
-        let modified_time = entry.header().mtime()?;
-        tx.send((path.to_path_buf(), buf, modified_time))?;
-        progress.tx.send(utils::ProgressData::Data((
-            path.to_string_lossy().to_string(),
-            size,
-        )))?;
-    }
+        Ok(())
+    })?;
 
     progress.join()?;
 
     drop(tx);
-    if let Err(e) = thread.join() {
-        Err(Error::msg(format!(
-            "Failed to join thread for extracting files from tar archive: {:?}",
-            e
-        )))
-    } else {
-        Ok(())
+    match thread.join() {
+        Err(e) => {
+            return Err(Error::msg(format!(
+                "Failed to join thread for extracting files from tar archive: {:?}",
+                e
+            )));
+        }
+        Ok(result) => result?,
+    }
+
+    // Links after the files, so no file is written through a symlink from the archive and
+    // hard link targets exist. Links are not created through the symlinks created before them
+    // either, which can point anywhere
+
+    let mut symlinks = std::collections::HashSet::new();
+    for (rel_path, link, metadata) in &links {
+        let parents = match link {
+            Link::Hard(target) => vec![rel_path, target],
+            Link::Symbolic(_) => vec![rel_path],
+        };
+        if let Some(e) = parents.into_iter().find_map(|path| {
+            paths::check_parents(path, &symlinks, options.allow_unsafe_paths).err()
+        }) {
+            rejected.push(e);
+            continue;
+        }
+        let dest_path = dest_dir.join(rel_path);
+        link.create(dest_dir, &dest_path, metadata)
+            .with_context(|| format!("Failed to create link {:?}", dest_path))?;
+        if let Link::Symbolic(_) = link {
+            symlinks.insert(rel_path.clone());
+        }
     }
+
+    // Directory metadata last, writing the files would overwrite the times and a read-only
+    // mode would prevent the files from being written at all
+
+    for (dest_path, metadata) in &directories {
+        metadata
+            .apply_to_path(dest_path)
+            .with_context(|| format!("Failed to set metadata of {:?}", dest_path))?;
+    }
+
+    if !rejected.is_empty() {
+        return Err(Error::msg(format!(
+            "Rejected {} unsafe entries in tar archive: {:?}",
+            rejected.len(),
+            rejected
+        )));
+    }
+
+    selection.check_unmatched()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::tests;
+    use std::io::Write;
 
     #[test]
     fn test_tar_zstd() {
         let mut tester = tests::tests::Tester::new();
 
-        tar_zstd(
-            &tester.src_dir.path(),
+        tar(
+            Codec::Zstd,
+            &tester.sources(),
             &mut tester.intermediate,
-            3,
-            false,
-            10 * 1024 * 1024,
+            &CompressOptions::default(),
             0,
         )
         .unwrap();
 
         tester.flush_intermediate();
 
-        untar_zstd(&mut tester.intermediate, &tester.dest_dir.path(), 0).unwrap();
+        untar(
+            Codec::Zstd,
+            &mut tester.intermediate,
+            tester.dest_dir.path(),
+            &ExtractOptions::default(),
+            0,
+        )
+        .unwrap();
 
         tester.assert();
     }
+
+    #[test]
+    fn test_tar_codecs() {
+        for codec in [
+            Codec::Gzip,
+            Codec::Xz,
+            Codec::Bzip2,
+            Codec::Lz4,
+            Codec::Uncompressed,
+        ] {
+            let mut tester = tests::tests::Tester::new();
+            let options = CompressOptions::default();
+            tar(
+                codec,
+                &tester.sources(),
+                &mut tester.intermediate,
+                &options,
+                0,
+            )
+            .unwrap();
+            tester.flush_intermediate();
+
+            let options = ExtractOptions::default();
+            untar(
+                codec,
+                &mut tester.intermediate,
+                tester.dest_dir.path(),
+                &options,
+                0,
+            )
+            .unwrap();
+            tester.assert();
+        }
+    }
+
+    #[test]
+    fn test_tar_many_files() {
+        // More files than the channel to the writer holds, so the readers wait on it while the
+        // encoder compresses a batch. An encoder sharing the pool of the readers hangs here once
+        // the pool has a few threads, as with RAYON_NUM_THREADS=4
+        let src_dir = tempfile::tempdir().unwrap();
+        let mut seed = 1u64;
+        for i in 0..1000 {
+            let data = (0..4096)
+                .map(|_| {
+                    seed = seed
+                        .wrapping_mul(6364136223846793005)
+                        .wrapping_add(1442695040888963407);
+                    (seed >> 56) as u8
+                })
+                .collect::<Vec<_>>();
+            std::fs::write(src_dir.path().join(format!("{:04}", i)), data).unwrap();
+        }
+        let before_hash = tests::tests::calculate_hash(src_dir.path()).unwrap();
+
+        for (codec, options) in [
+            (Codec::Zstd, CompressOptions::default()),
+            (Codec::Gzip, CompressOptions::default()),
+            (
+                Codec::Zstd,
+                CompressOptions {
+                    frame_size: 64 * 1024,
+                    ..Default::default()
+                },
+            ),
+            (
+                Codec::Zstd,
+                CompressOptions {
+                    frame_size: 64 * 1024,
+                    adapt: Some((1, 19)),
+                    ..Default::default()
+                },
+            ),
+        ] {
+            let sources = vec![(src_dir.path().to_path_buf(), std::path::PathBuf::new())];
+            let (tx, rx) = std::sync::mpsc::channel();
+            std::thread::spawn(move || {
+                let dest_dir = tempfile::tempdir().unwrap();
+                let mut intermediate = std::io::Cursor::new(Vec::new());
+                tar(codec, &sources, &mut intermediate, &options, 0).unwrap();
+                intermediate.set_position(0);
+                let options = ExtractOptions::default();
+                untar(codec, &mut intermediate, dest_dir.path(), &options, 0).unwrap();
+                tx.send(tests::tests::calculate_hash(dest_dir.path()).unwrap())
+                    .unwrap();
+            });
+            let after_hash = rx
+                .recv_timeout(std::time::Duration::from_secs(120))
+                .unwrap_or_else(|_| panic!("{} did not finish", codec.name()));
+            assert_eq!(before_hash, after_hash, "{}", codec.name());
+        }
+    }
+
+    #[test]
+    fn test_tar_zstd_dictionary() {
+        let samples = (0..200)
+            .map(|i| format!("This is a small test file number {} in a subdirectory.", i))
+            .collect::<Vec<_>>();
+        let dictionary = zstd::dict::from_samples(&samples, 4096).unwrap();
+        let dict_dir = tempfile::tempdir().unwrap();
+        let dict_path = dict_dir.path().join("dict");
+        std::fs::write(&dict_path, dictionary).unwrap();
+
+        // A single frame and independent frames with the dictionary given again, a single frame
+        // with it embedded
+        for (frame_size, embed_dictionary) in [(0, false), (64 * 1024, false), (0, true)] {
+            let mut tester = tests::tests::Tester::new();
+            let options = CompressOptions {
+                frame_size,
+                dictionary: Some(dict_path.clone()),
+                embed_dictionary,
+                ..Default::default()
+            };
+            tar(
+                Codec::Zstd,
+                &tester.sources(),
+                &mut tester.intermediate,
+                &options,
+                0,
+            )
+            .unwrap();
+            tester.flush_intermediate();
+
+            if !embed_dictionary {
+                let result = untar(
+                    Codec::Zstd,
+                    &mut tester.intermediate,
+                    tester.dest_dir.path(),
+                    &ExtractOptions::default(),
+                    0,
+                );
+                assert!(format!("{:#}", result.unwrap_err()).contains("--dict"));
+                tester.flush_intermediate();
+            }
+
+            let options = ExtractOptions {
+                dictionary: (!embed_dictionary).then(|| dict_path.clone()),
+                ..Default::default()
+            };
+            untar(
+                Codec::Zstd,
+                &mut tester.intermediate,
+                tester.dest_dir.path(),
+                &options,
+                0,
+            )
+            .unwrap();
+            tester.assert();
+
+            if frame_size > 0 {
+                let dictionary = std::fs::read(&dict_path).unwrap();
+                let mut reader = frames::SeekableReader::open(&mut tester.intermediate, dictionary)
+                    .unwrap()
+                    .unwrap();
+                let mut count = 0;
+                list_members(&mut reader, |_| {
+                    count += 1;
+                    Ok(())
+                })
+                .unwrap();
+                assert_eq!(count, reader.members.len());
+            }
+        }
+    }
+
+    #[test]
+    fn test_tar_zstd_frames() {
+        let mut tester = tests::tests::Tester::new();
+        let options = CompressOptions {
+            frame_size: 64 * 1024,
+            ..Default::default()
+        };
+        tar(
+            Codec::Zstd,
+            &tester.sources(),
+            &mut tester.intermediate,
+            &options,
+            0,
+        )
+        .unwrap();
+        tester.flush_intermediate();
+
+        let failed = verify_tar(
+            Codec::Zstd,
+            &mut tester.intermediate,
+            &ExtractOptions::default(),
+            0,
+        )
+        .unwrap();
+        assert!(failed.is_empty());
+        tester.flush_intermediate();
+
+        let options = ExtractOptions::default();
+        untar(
+            Codec::Zstd,
+            &mut tester.intermediate,
+            tester.dest_dir.path(),
+            &options,
+            0,
+        )
+        .unwrap();
+        tester.assert();
+    }
+
+    #[test]
+    fn test_tar_zstd_single_file() {
+        let tester = tests::tests::Tester::new();
+        let src_file = tester.src_dir.path().join("dir/test_small.txt");
+        let mut intermediate = std::io::Cursor::new(Vec::new());
+        let sources = [(src_file.clone(), std::path::PathBuf::new())];
+        tar(
+            Codec::Zstd,
+            &sources,
+            &mut intermediate,
+            &CompressOptions::default(),
+            0,
+        )
+        .unwrap();
+        intermediate.set_position(0);
+
+        untar(
+            Codec::Zstd,
+            &mut intermediate,
+            tester.dest_dir.path(),
+            &ExtractOptions::default(),
+            0,
+        )
+        .unwrap();
+
+        let names = std::fs::read_dir(tester.dest_dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["test_small.txt"]);
+        assert_eq!(
+            std::fs::read(tester.dest_dir.path().join("test_small.txt")).unwrap(),
+            std::fs::read(&src_file).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_tar_zstd_sources() {
+        let tester = tests::tests::Tester::new();
+        let src_file = tester.src_dir.path().join("test_small.txt");
+        let sources = [
+            (tester.src_dir.path().to_path_buf(), "data".into()),
+            (src_file.clone(), "extra/small.txt".into()),
+        ];
+        let mut intermediate = std::io::Cursor::new(Vec::new());
+        tar(
+            Codec::Zstd,
+            &sources,
+            &mut intermediate,
+            &CompressOptions::default(),
+            0,
+        )
+        .unwrap();
+        intermediate.set_position(0);
+
+        untar(
+            Codec::Zstd,
+            &mut intermediate,
+            tester.dest_dir.path(),
+            &ExtractOptions::default(),
+            0,
+        )
+        .unwrap();
+
+        let dest = tester.dest_dir.path();
+        let after_hash = tests::tests::calculate_hash(&dest.join("data")).unwrap();
+        assert_eq!(after_hash, tester.before_hash);
+        assert!(dest.join("data/dir/empty").is_dir());
+        assert_eq!(
+            std::fs::read(dest.join("extra/small.txt")).unwrap(),
+            std::fs::read(&src_file).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_zstd_parameters() {
+        let data = (0..4_000_000u32)
+            .map(|i| (i % 7919) as u8)
+            .collect::<Vec<_>>();
+        let options = [
+            CompressOptions {
+                compress_level: -5,
+                zstd_threads: 1,
+                checksum: false,
+                ..Default::default()
+            },
+            // Wider than the default window limit of decoders
+            CompressOptions {
+                window_log: Some(28),
+                zstd_threads: 2,
+                job_size: Some(1024 * 1024),
+                overlap_log: Some(9),
+                ..Default::default()
+            },
+        ];
+        for options in options {
+            let mut zstd_encoder = encoder(Vec::new(), &options).unwrap();
+            zstd_encoder.write_all(&data).unwrap();
+            let compressed = zstd_encoder.finish().unwrap();
+
+            // The frame header descriptor flags the checksum
+            assert_eq!(compressed[4] & 0x04 != 0, options.checksum);
+
+            let mut decompressed = Vec::new();
+            decoder(compressed.as_slice(), &[])
+                .unwrap()
+                .read_to_end(&mut decompressed)
+                .unwrap();
+            assert_eq!(decompressed, data);
+        }
+    }
+
+    #[test]
+    fn test_compress_zst() {
+        let tester = tests::tests::Tester::new();
+        let src_file = tester.src_dir.path().join("test_big.txt");
+        let mut compressed = Vec::new();
+        compress_zst(&src_file, &mut compressed, &CompressOptions::default(), 0).unwrap();
+
+        // Readable by plain zstd, without any tar header
+        assert_eq!(
+            zstd::decode_all(compressed.as_slice()).unwrap(),
+            std::fs::read(&src_file).unwrap()
+        );
+        let mut decompressed = Vec::new();
+        decompress_zst(&mut compressed.as_slice(), &mut decompressed).unwrap();
+        assert_eq!(decompressed, std::fs::read(&src_file).unwrap());
+        assert!(verify_zst(&mut compressed.as_slice()).unwrap().is_empty());
+
+        let middle = compressed.len() / 2;
+        compressed[middle] ^= 0xFF;
+        assert_eq!(verify_zst(&mut compressed.as_slice()).unwrap().len(), 1);
+
+        let mut output = Vec::new();
+        let options = CompressOptions::default();
+        assert!(compress_zst(tester.src_dir.path(), &mut output, &options, 0).is_err());
+    }
+
+    #[test]
+    fn test_list_tar_zstd() {
+        let tester = tests::tests::Tester::new();
+        let mut intermediate = std::io::Cursor::new(Vec::new());
+        tar(
+            Codec::Zstd,
+            &tester.sources(),
+            &mut intermediate,
+            &CompressOptions::default(),
+            0,
+        )
+        .unwrap();
+        intermediate.set_position(0);
+
+        let mut entries = Vec::new();
+        list_tar(Codec::Zstd, &mut intermediate, None, |entry| {
+            entries.push(entry);
+            Ok(())
+        })
+        .unwrap();
+
+        let files = entries
+            .iter()
+            .filter(|entry| entry.kind == list::Kind::File)
+            .collect::<Vec<_>>();
+        assert_eq!(files.len(), tester.before_hash.len());
+        for entry in files {
+            let path = tester.src_dir.path().join(&entry.path);
+            assert_eq!(entry.size, std::fs::metadata(path).unwrap().len());
+        }
+        assert!(
+            entries
+                .iter()
+                .any(|entry| entry.kind == list::Kind::Directory && entry.path == "dir/empty")
+        );
+    }
+
+    #[test]
+    fn test_verify_tar_zstd() {
+        let tester = tests::tests::Tester::new();
+        let mut intermediate = std::io::Cursor::new(Vec::new());
+        tar(
+            Codec::Zstd,
+            &tester.sources(),
+            &mut intermediate,
+            &CompressOptions::default(),
+            0,
+        )
+        .unwrap();
+
+        let mut archive = intermediate.into_inner();
+        assert!(
+            verify_tar(
+                Codec::Zstd,
+                &mut archive.as_slice(),
+                &ExtractOptions::default(),
+                0,
+            )
+            .unwrap()
+            .is_empty()
+        );
+
+        let middle = archive.len() / 2;
+        archive[middle] ^= 0xFF;
+        assert_eq!(
+            verify_tar(
+                Codec::Zstd,
+                &mut archive.as_slice(),
+                &ExtractOptions::default(),
+                0,
+            )
+            .unwrap()
+            .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_tar_zstd_reproducible() {
+        let tester = tests::tests::Tester::new();
+        let options = CompressOptions {
+            reproducible: true,
+            source_date_epoch: Some(1_500_000_000),
+            ..Default::default()
+        };
+        let archive = || {
+            let mut intermediate = std::io::Cursor::new(Vec::new());
+            tar(
+                Codec::Zstd,
+                &tester.sources(),
+                &mut intermediate,
+                &options,
+                0,
+            )
+            .unwrap();
+            intermediate.into_inner()
+        };
+
+        let first = archive();
+        assert_eq!(first, archive());
+
+        let decoded = zstd::decode_all(first.as_slice()).unwrap();
+        let mut tar_archive = tar::Archive::new(decoded.as_slice());
+        let mut paths = Vec::new();
+        for entry in tar_archive.entries().unwrap() {
+            let entry = entry.unwrap();
+            let header = entry.header();
+            assert!(header.mtime().unwrap() <= 1_500_000_000);
+            assert_eq!(header.uid().unwrap(), 0);
+            assert_eq!(header.username().unwrap(), Some(""));
+            paths.push(entry.path().unwrap().into_owned());
+        }
+        let mut sorted = paths.clone();
+        sorted.sort();
+        assert_eq!(paths, sorted);
+    }
+
+    #[test]
+    fn test_tar_zstd_max_memory() {
+        // Files within the budget are buffered a few at a time, larger ones are streamed. Frames
+        // larger than the budget are decoded one at a time, the entries in them streamed
+        const LIMIT: u64 = 8192;
+        const FRAME_SIZE: u64 = 16 * 1024;
+        for (reproducible, frame_size) in [(false, 0), (true, 0), (false, FRAME_SIZE)] {
+            let mut tester = tests::tests::Tester::new();
+            for i in 0..20 {
+                let path = tester.src_dir.path().join(format!("mid_{}.bin", i));
+                std::fs::write(path, vec![i as u8; 3000 + i * 300]).unwrap();
+            }
+            tester.before_hash = tests::tests::calculate_hash(tester.src_dir.path()).unwrap();
+
+            let options = CompressOptions {
+                reproducible,
+                frame_size,
+                memory: utils::MemoryBudget::new(Some(LIMIT)),
+                ..Default::default()
+            };
+            tar(
+                Codec::Zstd,
+                &tester.sources(),
+                &mut tester.intermediate,
+                &options,
+                0,
+            )
+            .unwrap();
+            tester.flush_intermediate();
+            assert!(options.memory.peak() > 0);
+            assert!(options.memory.peak() <= LIMIT);
+
+            let options = ExtractOptions {
+                memory: utils::MemoryBudget::new(Some(LIMIT)),
+                ..Default::default()
+            };
+            untar(
+                Codec::Zstd,
+                &mut tester.intermediate,
+                tester.dest_dir.path(),
+                &options,
+                0,
+            )
+            .unwrap();
+            tester.assert();
+            assert!(options.memory.peak() > 0);
+            assert!(options.memory.peak() >= frame_size);
+            assert!(options.memory.peak() <= LIMIT + frame_size);
+            tester.flush_intermediate();
+
+            // Testing reads every file in pieces, the larger ones too, and keeps none of them,
+            // only the frame they are read from
+            let options = ExtractOptions {
+                memory: utils::MemoryBudget::new(Some(LIMIT)),
+                ..Default::default()
+            };
+            let failed = verify_tar(Codec::Zstd, &mut tester.intermediate, &options, 0).unwrap();
+            assert!(failed.is_empty());
+            assert_eq!(options.memory.peak(), frame_size);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_tar_max_memory_failed_entry() {
+        // The dangling symlink fails to read first, with the files after it over the budget
+        let src_dir = tempfile::tempdir().unwrap();
+        std::os::unix::fs::symlink("missing", src_dir.path().join("a")).unwrap();
+        for i in 0..20 {
+            std::fs::write(src_dir.path().join(format!("f{:02}", i)), [0u8; 4096]).unwrap();
+        }
+        let sources = vec![(src_dir.path().to_path_buf(), std::path::PathBuf::new())];
+        let options = CompressOptions {
+            follow_symlinks: true,
+            reproducible: true,
+            memory: utils::MemoryBudget::new(Some(8192)),
+            ..Default::default()
+        };
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let mut zip_output = std::io::Cursor::new(Vec::new());
+            let results = [
+                tar(Codec::Zstd, &sources, &mut std::io::sink(), &options, 0),
+                crate::zip::zip(&sources, &mut zip_output, &options, 0),
+            ];
+            tx.send(results.map(|result| result.is_err())).unwrap();
+        });
+        let failed = rx
+            .recv_timeout(std::time::Duration::from_secs(60))
+            .expect("did not finish");
+        assert_eq!(failed, [true, true]);
+    }
+
+    #[test]
+    fn test_untar_zstd_selected() {
+        let mut tester = tests::tests::Tester::new();
+        tar(
+            Codec::Zstd,
+            &tester.sources(),
+            &mut tester.intermediate,
+            &CompressOptions::default(),
+            0,
+        )
+        .unwrap();
+        tester.flush_intermediate();
+
+        let options = ExtractOptions {
+            patterns: vec!["dir/*.txt".to_string(), "test_small.txt".to_string()],
+            ..Default::default()
+        };
+        untar(
+            Codec::Zstd,
+            &mut tester.intermediate,
+            tester.dest_dir.path(),
+            &options,
+            0,
+        )
+        .unwrap();
+
+        let dest = tester.dest_dir.path();
+        assert!(dest.join("test_small.txt").is_file());
+        assert!(dest.join("dir/test_small.txt").is_file());
+        assert!(!dest.join("test_big.txt").exists());
+        assert!(!dest.join("dir/empty").exists());
+
+        tester.intermediate.set_position(0);
+        let options = ExtractOptions {
+            patterns: vec!["missing.txt".to_string()],
+            ..Default::default()
+        };
+        assert!(untar(Codec::Zstd, &mut tester.intermediate, dest, &options, 0).is_err());
+    }
+
+    #[test]
+    fn test_tar_zstd_members() {
+        let tester = tests::tests::Tester::new();
+        let mut intermediate = std::io::Cursor::new(Vec::new());
+        let options = CompressOptions {
+            frame_size: 64 * 1024,
+            ..Default::default()
+        };
+        tar(
+            Codec::Zstd,
+            &tester.sources(),
+            &mut intermediate,
+            &options,
+            0,
+        )
+        .unwrap();
+
+        // Listing through the index gives the entries of the stream
+        let list = |reader: &mut frames::SeekableReader<_>| {
+            let mut entries = Vec::new();
+            list_members(reader, |entry| {
+                entries.push((entry.path, entry.size));
+                Ok(())
+            })
+            .unwrap();
+            entries
+        };
+        let mut reader = frames::SeekableReader::open(&mut intermediate, Vec::new())
+            .unwrap()
+            .unwrap();
+        let entries = list(&mut reader);
+        intermediate.set_position(0);
+        let mut expected = Vec::new();
+        list_tar(Codec::Zstd, &mut intermediate, None, |entry| {
+            expected.push((entry.path, entry.size));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(entries, expected);
+
+        let options = ExtractOptions {
+            patterns: vec!["dir/*.txt".to_string(), "test_small.txt".to_string()],
+            ..Default::default()
+        };
+        let dest = tester.dest_dir.path();
+        let mut reader = frames::SeekableReader::open(&mut intermediate, Vec::new())
+            .unwrap()
+            .unwrap();
+        untar_members(&mut reader, dest, &options, 0).unwrap();
+        assert!(dest.join("test_small.txt").is_file());
+        assert!(dest.join("dir/test_small.txt").is_file());
+        assert!(!dest.join("test_big.txt").exists());
+        assert_eq!(
+            std::fs::read(dest.join("dir/test_small.txt")).unwrap(),
+            std::fs::read(tester.src_dir.path().join("dir/test_small.txt")).unwrap()
+        );
+
+        // Archives written as a single frame have no index
+        let mut intermediate = std::io::Cursor::new(Vec::new());
+        tar(
+            Codec::Zstd,
+            &tester.sources(),
+            &mut intermediate,
+            &CompressOptions::default(),
+            0,
+        )
+        .unwrap();
+        assert!(
+            frames::SeekableReader::open(&mut intermediate, Vec::new())
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_untar_zstd_rejects_unsafe_paths() {
+        let root = tempfile::tempdir().unwrap();
+        let dest_dir = root.path().join("dest");
+
+        let mut tar_builder = tar::Builder::new(Vec::new());
+        for name in ["../escaped.txt", "safe.txt"] {
+            let mut header = tar::Header::new_gnu();
+            header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_size(4);
+            header.set_mode(0o644);
+            header.set_cksum();
+            tar_builder.append(&header, &b"data"[..]).unwrap();
+        }
+        let tar_data = tar_builder.into_inner().unwrap();
+        let mut input = std::io::Cursor::new(zstd::encode_all(&tar_data[..], 3).unwrap());
+
+        let result = untar(
+            Codec::Zstd,
+            &mut input,
+            &dest_dir,
+            &ExtractOptions::default(),
+            0,
+        );
+
+        assert!(result.unwrap_err().to_string().contains("escaped.txt"));
+        assert!(!root.path().join("escaped.txt").exists());
+        assert!(dest_dir.join("safe.txt").exists());
+
+        // Each symlink stays inside on its own, but the later ones go through the earlier ones
+        #[cfg(unix)]
+        {
+            let dest_dir = root.path().join("dest/chained");
+            let mut tar_builder = tar::Builder::new(Vec::new());
+            for (name, target) in [("d/l", ".."), ("d/l/x", ".."), ("d/l/x/pwned", "anything")] {
+                let mut header = tar::Header::new_gnu();
+                header.set_entry_type(tar::EntryType::Symlink);
+                header.set_size(0);
+                header.set_mode(0o777);
+                tar_builder.append_link(&mut header, name, target).unwrap();
+            }
+            let tar_data = tar_builder.into_inner().unwrap();
+            let mut input = std::io::Cursor::new(zstd::encode_all(&tar_data[..], 3).unwrap());
+
+            let result = untar(
+                Codec::Zstd,
+                &mut input,
+                &dest_dir,
+                &ExtractOptions::default(),
+                0,
+            );
+
+            assert!(result.unwrap_err().to_string().contains("d/l/x"));
+            assert!(dest_dir.join("d/l").symlink_metadata().is_ok());
+            assert!(dest_dir.join("x").symlink_metadata().is_err());
+            assert!(root.path().join("dest/pwned").symlink_metadata().is_err());
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_untar_zstd_restores_metadata() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let src_dir = tempfile::tempdir().unwrap();
+        let dest_dir = tempfile::tempdir().unwrap();
+
+        let script = src_dir.path().join("bin/run.sh");
+        std::fs::create_dir_all(script.parent().unwrap()).unwrap();
+        std::fs::write(&script, "#!/bin/sh\necho ok\n").unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o750)).unwrap();
+        let mtime = filetime::FileTime::from_unix_time(1_600_000_000, 123_456_789);
+        let atime = filetime::FileTime::from_unix_time(1_600_000_100, 987_654_321);
+        filetime::set_file_times(&script, atime, mtime).unwrap();
+
+        let mut intermediate = std::io::Cursor::new(Vec::new());
+        let options = CompressOptions {
+            small_file_size: 0,
+            ..Default::default()
+        };
+        let sources = [(src_dir.path().to_path_buf(), std::path::PathBuf::new())];
+        tar(Codec::Zstd, &sources, &mut intermediate, &options, 0).unwrap();
+        intermediate.set_position(0);
+        untar(
+            Codec::Zstd,
+            &mut intermediate,
+            dest_dir.path(),
+            &ExtractOptions::default(),
+            0,
+        )
+        .unwrap();
+
+        let metadata = std::fs::metadata(dest_dir.path().join("bin/run.sh")).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o7777, 0o750);
+        assert_eq!(
+            filetime::FileTime::from_last_modification_time(&metadata),
+            mtime
+        );
+        assert_eq!(filetime::FileTime::from_last_access_time(&metadata), atime);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_tar_zstd_links() {
+        use std::os::unix::fs::MetadataExt;
+
+        let src_dir = tempfile::tempdir().unwrap();
+        let dest_dir = tempfile::tempdir().unwrap();
+
+        std::fs::create_dir_all(src_dir.path().join("dir")).unwrap();
+        std::fs::write(src_dir.path().join("dir/data.txt"), "linked data").unwrap();
+        std::fs::hard_link(
+            src_dir.path().join("dir/data.txt"),
+            src_dir.path().join("hard.txt"),
+        )
+        .unwrap();
+        std::os::unix::fs::symlink("dir/data.txt", src_dir.path().join("soft.txt")).unwrap();
+        std::os::unix::fs::symlink("missing.txt", src_dir.path().join("dangling.txt")).unwrap();
+
+        let mut intermediate = std::io::Cursor::new(Vec::new());
+        tar(
+            Codec::Zstd,
+            &[(src_dir.path().to_path_buf(), std::path::PathBuf::new())],
+            &mut intermediate,
+            &CompressOptions::default(),
+            0,
+        )
+        .unwrap();
+        intermediate.set_position(0);
+        untar(
+            Codec::Zstd,
+            &mut intermediate,
+            dest_dir.path(),
+            &ExtractOptions::default(),
+            0,
+        )
+        .unwrap();
+
+        let dest = dest_dir.path();
+        assert_eq!(
+            std::fs::read_link(dest.join("soft.txt")).unwrap(),
+            std::path::Path::new("dir/data.txt")
+        );
+        assert_eq!(
+            std::fs::read_link(dest.join("dangling.txt")).unwrap(),
+            std::path::Path::new("missing.txt")
+        );
+        assert_eq!(
+            std::fs::read_to_string(dest.join("hard.txt")).unwrap(),
+            "linked data"
+        );
+        assert_eq!(
+            std::fs::metadata(dest.join("hard.txt")).unwrap().ino(),
+            std::fs::metadata(dest.join("dir/data.txt")).unwrap().ino()
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_tar_zstd_xattrs() {
+        let src_dir = tempfile::tempdir().unwrap();
+        let file = src_dir.path().join("tagged.txt");
+        std::fs::write(&file, "tagged").unwrap();
+        if xattr::set(&file, "user.rpcc.test", b"value").is_err() {
+            // The temporary file system does not support user extended attributes
+            return;
+        }
+
+        let roundtrip = |xattrs: bool| {
+            let dest_dir = tempfile::tempdir().unwrap();
+            let mut intermediate = std::io::Cursor::new(Vec::new());
+            let options = CompressOptions {
+                xattrs,
+                ..Default::default()
+            };
+            let sources = [(src_dir.path().to_path_buf(), std::path::PathBuf::new())];
+            tar(Codec::Zstd, &sources, &mut intermediate, &options, 0).unwrap();
+            intermediate.set_position(0);
+            let options = ExtractOptions {
+                xattrs,
+                ..Default::default()
+            };
+            untar(Codec::Zstd, &mut intermediate, dest_dir.path(), &options, 0).unwrap();
+            xattr::get(dest_dir.path().join("tagged.txt"), "user.rpcc.test").unwrap()
+        };
+
+        assert_eq!(roundtrip(true), Some(b"value".to_vec()));
+        assert_eq!(roundtrip(false), None);
+    }
 }